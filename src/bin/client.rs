@@ -9,29 +9,48 @@ use std::{
 
 /* ---------- 外部依赖 ---------- */
 use anyhow::Result;
+use clap::{Parser, Subcommand};
 use colored::*;
 use crossterm::{
     execute,
     event::{self, Event as CEvent, EnableMouseCapture, DisableMouseCapture},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use tempfile;
 use tokio::sync::mpsc as tokio_mpsc;
 use tui::{
     backend::CrosstermBackend,
-    widgets::ListState,
     Terminal,
 };
 
 /* ---------- 本地 crate ---------- */
 use rust_chat::client::{
-    utils::inviation_clear,
-    network,
-    receiver::{drain_messages, ChatMessage},
-    initialization::{initial_serveraddr, initial_name,init_color},
+    addr::ServerAddr,
+    utils::{inviation_clear, parse_invitation},
+    receiver::drain_messages,
+    network::Incoming,
+    servers::Servers,
+    initialization::{cached_master, initial_serveraddr, initial_name, init_color, offer_save_server, saved_server_password},
+    config,
     handshake,
-    keyboard::{handle_key, UndoMgr, KeyCtx, ControlFlow},
+    keyboard::{handle_key, KeyCtx, ControlFlow},
+    cli,
 };
+
+/// 不带子命令时进入交互界面
+#[derive(Parser, Debug)]
+#[command(about = "Encrypted chat client")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 发送一条消息后退出
+    Send(cli::SendArgs),
+    /// 持续输出房间消息
+    Listen(cli::ListenArgs),
+}
 /// 第 n 个字形单元（grapheme）在字符串中的字节偏移
 // ================== UI 事件枚举 ==================
 #[derive(Debug)]
@@ -47,7 +66,20 @@ enum UiMode {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
+    if let Some(command) = args.command {
+        // 非交互模式：配置只用来取默认昵称，读不到就算了
+        let _ = config::load();
+        let code = match command {
+            Command::Send(a)   => cli::send(a).await,
+            Command::Listen(a) => cli::listen(a).await,
+        };
+        std::process::exit(code as i32);
+    }
     init_color();
+    if let Err(e) = config::load() {
+        eprintln!("⚠️ Failed to load config, using defaults: {e}");
+    }
     let username = initial_name()?;
     let mut server_addr =initial_serveraddr()?;
     /* ---------- 1. 在这里初始化用户名和服务器 ---------- */
@@ -56,20 +88,17 @@ async fn main() -> Result<()> {
     //TODO：用户在选择房间界面，能否按下Esc回到服务器选择界面
     //因为用户一旦连接上了某个服务器后就无法使用邀请码进入房间了
     
-    /* ---------- 2. 网络 <-> UI 的通道 ---------- */
-    let (net_tx, mut net_rx) = tokio_mpsc::unbounded_channel::<String>(); // 网络 → UI
-    let (out_tx, out_rx) = tokio_mpsc::unbounded_channel::<String>();     // UI → 网络
+    /* ---------- 2. 网络 → UI 的通道（所有服务器共用） ---------- */
+    let (net_tx, mut net_rx) = tokio_mpsc::unbounded_channel::<(usize, Incoming)>();
 
     //得到服务器地址后开始握手
-    let (lines, writer, room_id,pwd) = loop {
+    let login = loop {
         if server_addr.is_empty() {
             let new_addr = initial_serveraddr()?;
             server_addr = new_addr;
         }
         match handshake::connect_and_login(&server_addr, &username).await {
-            Ok((lines, writer, room_id,pwd)) => {
-                break (lines, writer, room_id,pwd);
-            }
+            Ok(login) => break login,
             Err(e) if e.to_string().contains("邀请码无效") => {
                 eprintln!("❌ 邀请码无效或已过期，请重新选择服务器或输入新的邀请码。\n");
                 server_addr.clear();
@@ -87,15 +116,14 @@ async fn main() -> Result<()> {
         }
         
     };
+    offer_save_server(&server_addr)?;
+    let label = server_label(&server_addr);
     //特性：受邀请者退出房间后回到服务器选择界面，而且在房间中无法生成正确的邀请码
     server_addr=inviation_clear(&server_addr);
 
-    /* ---------- 3. 启动网络任务（自动重连 + 心跳） ---------- */
-    tokio::spawn(async move {
-        if let Err(e) = network::chat_loop(lines, writer, net_tx, out_rx).await {
-            eprintln!("chat_loop error: {e}");
-        }
-    });
+    /* ---------- 3. 启动网络任务（心跳） ---------- */
+    let mut servers = Servers::new(net_tx);
+    servers.spawn(label, server_addr.clone(), login);
 
     /* ---------- 4. 终端 UI 初始化 ---------- */
     enable_raw_mode()?;
@@ -130,58 +158,52 @@ async fn main() -> Result<()> {
 
     /* ---------- 6. 应用状态 ---------- */
     let ui_mode = UiMode::Chat;
-    let mut messages: Vec<ChatMessage> = Vec::new();
-    let mut member_list: Vec<String>   = Vec::new();
-    let mut input = String::new();
-    let mut cursor = 0usize;
-    let mut list_state = ListState::default();
     let img_tempdir = tempfile::Builder::new()
         .prefix("")
         .tempdir()?;
-    let mut undo_mgr = UndoMgr::new();
     use rust_chat::client::ui::{draw_chat};
     /* ---------- 7. 主循环 ---------- */
     'ui: loop {
         terminal.draw(|f| {
             match ui_mode {
-                UiMode::Chat => draw_chat(
-                    f,
-                    &messages,
-                    &mut list_state,
-                    &member_list,
-                    &input,
-                    cursor,
-                    &username,
-                    &room_id,
-                ),
+                UiMode::Chat => draw_chat(f, &mut servers),
                 UiMode::_ImagePreview(_) => { /* 这里什么也不画，draw_image 会接管 */ }
             }
         })?;
         // ——— 处理键盘事件 ———
-        match ev_rx.recv() {
-            Ok(Event::Input(key)) => {
-                let mut ctx = KeyCtx {
-                    input:       &mut input,
-                    cursor:      &mut cursor,
-                    list_state:  &mut list_state,
-                    messages:    &mut messages,
-                    member_list: &mut member_list,
-                    undo_mgr:    &mut undo_mgr,
-                    out_tx:      &out_tx,
-                    server_addr: &mut server_addr,
-                    room_id:     &room_id,
-                    pwd:         &pwd,
-                    username:    &username,
-                };
-                if let ControlFlow::Quit = handle_key(key, &mut ctx) {
-                    break 'ui;
+        if let Ok(Event::Input(key)) = ev_rx.recv() {
+            let conn = servers.current_mut();
+            let mut ctx = KeyCtx {
+                tabs:        &mut conn.tabs,
+                out_tx:      &conn.out_tx,
+                server_addr: &conn.server_addr,
+                username:    &conn.nickname,
+            };
+            match handle_key(key, &mut ctx) {
+                ControlFlow::Continue => {}
+                ControlFlow::Quit => {
+                    // 该服务器最后一个房间已关闭
+                    servers.remove_current();
+                    if servers.is_empty() {
+                        break 'ui;
+                    }
                 }
+                ControlFlow::Connect(target) => {
+                    match connect_more(&target, &username).await {
+                        Ok((label, addr, login)) => servers.spawn(label, addr, login),
+                        Err(e) => {
+                            let tabs = &mut servers.current_mut().tabs;
+                            tabs.current_mut().push_system(&format!("Connect failed: {e}"));
+                        }
+                    }
+                }
+                ControlFlow::NextServer => servers.next(),
+                ControlFlow::PrevServer => servers.prev(),
             }
-            _ => {}
         }
 
         // ——— 收网络消息 ———
-        drain_messages(&mut net_rx, &mut messages, &mut list_state, &username,img_tempdir.path(),&mut member_list,);
+        drain_messages(&mut net_rx, &mut servers, img_tempdir.path());
     }
     
     /* ---------- 8. 清理退出 ---------- */
@@ -194,8 +216,49 @@ async fn main() -> Result<()> {
     )?;
     disable_raw_mode()?;
     terminal.show_cursor()?;
-    println!("{} [{}]","❌ 退出房间",room_id);
+    println!("❌ 已断开全部服务器");
     println!("{}","========Press Crtl + C to quit========\n".red().bold());
     continue;
     }
 }
+
+/// 侧边栏显示名：地址簿里有名字就用名字
+fn server_label(server_addr_or_invite: &str) -> String {
+    if let Some((addr, _, _, _)) = parse_invitation(server_addr_or_invite) {
+        return addr.to_string();
+    }
+    let addr = server_addr_or_invite.split('&').next().unwrap_or_default();
+    config::current()
+        .find_server(addr)
+        .map(|s| s.name.clone())
+        .unwrap_or_else(|| addr.to_string())
+}
+
+/// 聊天界面里 `/connect <host[:port]> [server_pwd] [room] [pwd]` 或 `/connect /INVITE:…`
+/// 省略服务器密码时使用地址簿中保存的密码
+async fn connect_more(target: &str, username: &str) -> Result<(String, String, handshake::Login)> {
+    let login = async {
+        if target.starts_with("/INVITE:") {
+            let login = handshake::connect_and_login(target, username).await?;
+            return Ok((server_label(target), String::new(), login));
+        }
+        let mut it = target.split_whitespace();
+        let host = it.next().unwrap_or_default();
+        let addr: ServerAddr = host.parse()?;
+        // 没给服务器密码时用地址簿里保存的，找不到就提示用法
+        let server_pwd = match it.next() {
+            Some(p) => p.to_string(),
+            None => saved_server_password(&config::current(), host, &addr, cached_master())
+                .map_err(|e| anyhow::anyhow!("{e}; usage: /connect <host[:port]> <server_pwd> [room] [pwd]"))?,
+        };
+        let default_room = config::current().room.clone().unwrap_or_else(|| "Public".to_string());
+        let room = it.next().unwrap_or(&default_room);
+        let pwd = it.next().unwrap_or("");
+        let login = handshake::connect_direct(&addr, &server_pwd, room, pwd, username).await?;
+        let server_addr = format!("{addr}&{server_pwd}");
+        Ok((server_label(&server_addr), server_addr, login))
+    };
+    tokio::time::timeout(Duration::from_secs(10), login)
+        .await
+        .map_err(|_| anyhow::anyhow!("handshake timed out"))?
+}
@@ -0,0 +1,95 @@
+// src/bin/echo_bot.rs
+//! 示例机器人：复述房间里的消息，支持 `!echo`、`!roll`、`!help`
+//!
+//! ```text
+//! CHAT_SERVER_PASSWORD=... echo_bot --server chat.example.lan --room Public --room ops
+//! ```
+//! 口令与 `client send` 相同，从环境变量读取；所有房间共用 `CHAT_ROOM_PASSWORD`（不设为空密码）。
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use rand::Rng;
+
+use rust_chat::client::{
+    addr::ServerAddr,
+    bot::{Bot, BotRunner, Command, Context},
+    cli::{ROOM_PASSWORD_ENV, SERVER_PASSWORD_ENV},
+    Credentials,
+};
+
+#[derive(Parser)]
+#[command(about = "Sample rust_chat bot that echoes messages and rolls dice")]
+struct Args {
+    #[arg(long)]
+    server: ServerAddr,
+    /// 要加入的房间，可重复
+    #[arg(long, required = true)]
+    room: Vec<String>,
+    #[arg(long, default_value = "echo")]
+    nick: String,
+    /// 只响应命令，不复述普通消息
+    #[arg(long)]
+    quiet: bool,
+}
+
+struct EchoBot {
+    quiet: bool,
+}
+
+impl Bot for EchoBot {
+    fn commands(&self) -> &[(&str, &str)] {
+        &[("echo <text>", "repeat the text"), ("roll [NdM]", "roll N dice with M sides, default 1d6")]
+    }
+
+    fn on_message(&mut self, ctx: &mut Context, sender: &str, text: &str) {
+        if !self.quiet {
+            ctx.reply(format!("{sender} said: {text}"));
+        }
+    }
+
+    fn on_command(&mut self, ctx: &mut Context, sender: &str, cmd: &Command) {
+        match cmd.name {
+            "echo" => ctx.reply(cmd.rest),
+            "roll" => match roll(cmd.args.first().copied().unwrap_or("1d6")) {
+                Ok((dice, total)) => ctx.reply(format!("{sender} rolled {dice:?} = {total}")),
+                Err(e) => ctx.reply(format!("{sender}: {e}")),
+            },
+            other => ctx.reply(format!("unknown command !{other}, try !help")),
+        }
+    }
+
+    fn on_join(&mut self, ctx: &mut Context, nick: &str) {
+        ctx.reply(format!("welcome, {nick}!"));
+    }
+
+    fn on_leave(&mut self, ctx: &mut Context, nick: &str) {
+        ctx.reply(format!("bye, {nick}"));
+    }
+}
+
+/// `NdM` → 每个骰子的点数与总和
+fn roll(spec: &str) -> Result<(Vec<u32>, u32)> {
+    let (n, m) = spec.split_once(['d', 'D']).ok_or_else(|| anyhow!("expected NdM, e.g. 2d6"))?;
+    let n: u32 = if n.is_empty() { 1 } else { n.parse()? };
+    let m: u32 = m.parse()?;
+    if !(1..=20).contains(&n) || !(2..=1000).contains(&m) {
+        bail!("up to 20 dice with 2..1000 sides");
+    }
+    let mut rng = rand::rng();
+    let dice: Vec<u32> = (0..n).map(|_| rng.random_range(1..=m)).collect();
+    let total = dice.iter().sum();
+    Ok((dice, total))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let server_pwd = std::env::var(SERVER_PASSWORD_ENV)
+        .map_err(|_| anyhow!("no server password: set {SERVER_PASSWORD_ENV}"))?;
+    let room_pwd = std::env::var(ROOM_PASSWORD_ENV).unwrap_or_default();
+
+    let mut runner = BotRunner::new(args.server, Credentials::new(server_pwd, args.nick));
+    for room in args.room {
+        runner = runner.room(room, room_pwd.clone());
+    }
+    runner.run(&mut EchoBot { quiet: args.quiet }).await
+}
@@ -0,0 +1,49 @@
+// src/bin/irc_gateway.rs
+//! IRC 网关入口：在本机端口说 IRC，把每个 IRC 连接映射成一个加密聊天会话
+//!
+//! ```text
+//! irc_gateway --server chat.example.lan:6655
+//! irssi -c 127.0.0.1 -p 6667 -w <服务器口令>    # 然后 /join #ops <房间密码>
+//! ```
+//! IRC 客户端不发 PASS 时使用 `CHAT_SERVER_PASSWORD` 或 `--server-password-file`。
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use rust_chat::client::{
+    addr::ServerAddr,
+    cli::SERVER_PASSWORD_ENV,
+    irc::{Gateway, DEFAULT_IRC_BIND},
+};
+
+#[derive(Parser)]
+#[command(about = "Let IRC clients join encrypted rust_chat rooms")]
+struct Args {
+    /// 聊天服务器地址
+    #[arg(long)]
+    server: ServerAddr,
+    /// IRC 监听地址；网关持有房间密钥，只监听本机
+    #[arg(long, default_value = DEFAULT_IRC_BIND)]
+    bind: String,
+    /// 默认服务器口令文件（否则读 CHAT_SERVER_PASSWORD；都没有时 IRC 客户端必须发 PASS）
+    #[arg(long)]
+    server_password_file: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let password = match &args.server_password_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("{}: {e}", path.display()))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        ),
+        None => std::env::var(SERVER_PASSWORD_ENV).ok(),
+    };
+    let gateway = Gateway::bind(&args.bind, args.server, password).await?;
+    eprintln!("irc gateway listening on {}", gateway.local_addr()?);
+    gateway.run().await
+}
@@ -0,0 +1,41 @@
+// src/bin/webhook.rs
+//! webhook 入口：在本机 HTTP 端口接收 `{room, text}`，以加密消息发到房间
+//!
+//! ```text
+//! CHAT_SERVER_PASSWORD=... webhook --config webhook.toml
+//! curl -H "Authorization: Bearer $TOKEN" -d '{"room":"ops","text":"deploy done"}' http://127.0.0.1:8787/message
+//! ```
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use rust_chat::client::{
+    cli::SERVER_PASSWORD_ENV,
+    webhook::{Webhook, WebhookConfig},
+};
+
+#[derive(Parser)]
+#[command(about = "Forward authenticated HTTP posts into rust_chat rooms")]
+struct Args {
+    /// 配置文件（TOML），见 webhook.example.toml
+    #[arg(short, long)]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let cfg = WebhookConfig::load(&args.config)?;
+    let server_password = match &cfg.password_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("{}: {e}", path.display()))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        None => std::env::var(SERVER_PASSWORD_ENV)
+            .map_err(|_| anyhow!("no server password: set password_file or {SERVER_PASSWORD_ENV}"))?,
+    };
+    let webhook = Webhook::start(cfg, server_password).await?;
+    eprintln!("webhook listening on http://{}/message", webhook.local_addr()?);
+    webhook.run().await
+}
@@ -0,0 +1,126 @@
+// client/addr.rs
+//! 服务器地址：域名 / IPv4 / `[IPv6]`，端口可省略（默认 6655）
+use std::{
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const DEFAULT_PORT: u16 = 6655;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServerAddr {
+    /// 域名或 IP；IPv6 不含方括号
+    pub host: String,
+    pub port: u16,
+}
+
+impl ServerAddr {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self { host: host.into(), port }
+    }
+
+    /// 解析地址，缺省端口时使用 `default_port`
+    pub fn parse_with_port(s: &str, default_port: u16) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            bail!("empty address");
+        }
+
+        // ① [IPv6] / [IPv6]:port
+        if let Some(rest) = s.strip_prefix('[') {
+            let (ip, tail) = rest.split_once(']').ok_or_else(|| anyhow!("missing ']' in {s}"))?;
+            let ip: Ipv6Addr = ip.parse().map_err(|_| anyhow!("invalid IPv6 address: {ip}"))?;
+            let port = match tail {
+                "" => default_port,
+                t  => parse_port(t.strip_prefix(':').ok_or_else(|| anyhow!("unexpected '{t}' after ']'"))?)?,
+            };
+            return Ok(Self::new(ip.to_string(), port));
+        }
+
+        // ② 不带方括号的 IPv6 只能省略端口
+        if s.matches(':').count() > 1 {
+            let ip: Ipv6Addr = s.parse().map_err(|_| anyhow!("invalid address: {s} (wrap IPv6 in [])"))?;
+            return Ok(Self::new(ip.to_string(), default_port));
+        }
+
+        // ③ host / host:port
+        let (host, port) = match s.split_once(':') {
+            Some((h, p)) => (h, parse_port(p)?),
+            None         => (s, default_port),
+        };
+        if host.parse::<IpAddr>().is_err() && !is_hostname(host) {
+            bail!("invalid host name: {host}");
+        }
+        Ok(Self::new(host.to_ascii_lowercase(), port))
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        self.host.parse::<Ipv6Addr>().is_ok()
+    }
+
+    pub async fn connect(&self) -> std::io::Result<TcpStream> {
+        TcpStream::connect((self.host.as_str(), self.port)).await
+    }
+
+    pub async fn bind(&self) -> std::io::Result<TcpListener> {
+        TcpListener::bind((self.host.as_str(), self.port)).await
+    }
+}
+
+fn parse_port(p: &str) -> Result<u16> {
+    p.parse().map_err(|_| anyhow!("invalid port: {p}"))
+}
+
+/// RFC 1123：字母数字与连字符组成的标签，以 `.` 分隔
+fn is_hostname(h: &str) -> bool {
+    let h = h.trim_end_matches('.');
+    // 纯数字的末级标签只可能是写错的 IPv4
+    let numeric_tld = h.rsplit('.').next().is_some_and(|l| l.chars().all(|c| c.is_ascii_digit()));
+    !h.is_empty()
+        && h.len() <= 253
+        && !numeric_tld
+        && h.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+impl FromStr for ServerAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse_with_port(s, DEFAULT_PORT)
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ipv6() {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+// 邀请码 / 配置里以字符串形式保存
+impl Serialize for ServerAddr {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerAddr {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,212 @@
+// client/bot.rs
+//! 聊天机器人框架：实现 [`Bot`]，交给 [`BotRunner`] 运行
+//!
+//! 机器人就是一个普通客户端，用房间密码派生的密钥收发，服务器看到的仍是密文。
+//! 框架负责连接、断线重连、`!命令` 解析与回复；回调是同步的，里面不要做耗时操作。
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::{bail, Result};
+use futures_util::StreamExt;
+
+use super::addr::ServerAddr;
+use super::headless::{Client, ClientEvent, Credentials};
+
+/// 默认命令前缀
+pub const DEFAULT_PREFIX: &str = "!";
+
+/// 首次连接失败时的重试退避上限（秒）
+const MAX_BACKOFF: u64 = 30;
+
+/// 解析出的命令：`!roll 2d6 fast` → name=`roll`，args=[`2d6`, `fast`]，rest=`2d6 fast`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command<'a> {
+    pub name: &'a str,
+    pub args: Vec<&'a str>,
+    /// 命令名之后的原文
+    pub rest: &'a str,
+}
+
+/// 以 `prefix` 开头的消息解析为命令；只有前缀或前缀后紧跟空白的不算
+pub fn parse_command<'a>(prefix: &str, text: &'a str) -> Option<Command<'a>> {
+    let body = text.trim().strip_prefix(prefix)?;
+    if body.is_empty() || body.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (name, rest) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+    let rest = rest.trim();
+    Some(Command { name, args: rest.split_whitespace().collect(), rest })
+}
+
+/// 回调的上下文：所在房间与要发出的回复
+pub struct Context {
+    room:   String,
+    me:     String,
+    outbox: Vec<(String, String)>,
+}
+
+impl Context {
+    fn new(room: &str, me: String) -> Self {
+        Self { room: room.to_string(), me, outbox: Vec::new() }
+    }
+
+    /// 事件所在房间
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    /// 机器人当前昵称
+    pub fn nickname(&self) -> &str {
+        &self.me
+    }
+
+    /// 在当前房间回复
+    pub fn reply(&mut self, text: impl Into<String>) {
+        self.outbox.push((self.room.clone(), text.into()));
+    }
+
+    /// 发到机器人加入的另一个房间
+    pub fn send_to(&mut self, room: impl Into<String>, text: impl Into<String>) {
+        self.outbox.push((room.into(), text.into()));
+    }
+}
+
+/// 机器人的回调；都有空的默认实现，按需覆盖
+///
+/// 自己发的消息和进房时补发的历史不会触发回调；别人发的图片走 `on_event`。
+pub trait Bot: Send {
+    /// 命令前缀
+    fn prefix(&self) -> &str {
+        DEFAULT_PREFIX
+    }
+
+    /// (用法, 说明)，如 `("roll [NdM]", "roll dice")`，用于内置的 `!help`；列出了 `help` 时不再内置
+    fn commands(&self) -> &[(&str, &str)] {
+        &[]
+    }
+
+    /// 普通消息（不以命令前缀开头）
+    fn on_message(&mut self, _ctx: &mut Context, _sender: &str, _text: &str) {}
+
+    /// 以命令前缀开头的消息
+    fn on_command(&mut self, _ctx: &mut Context, _sender: &str, _cmd: &Command) {}
+
+    /// 别人进入房间
+    fn on_join(&mut self, _ctx: &mut Context, _nick: &str) {}
+
+    /// 别人离开房间
+    fn on_leave(&mut self, _ctx: &mut Context, _nick: &str) {}
+
+    /// 其余事件（成员列表、被踢、断线重连等），`ctx.room()` 为空表示与房间无关
+    fn on_event(&mut self, _ctx: &mut Context, _event: &ClientEvent) {}
+}
+
+/// 连接服务器、进入房间并把事件分发给 [`Bot`]
+pub struct BotRunner {
+    addr:  ServerAddr,
+    creds: Credentials,
+    /// (房间, 房间密码)
+    rooms: Vec<(String, String)>,
+}
+
+impl BotRunner {
+    pub fn new(addr: ServerAddr, creds: Credentials) -> Self {
+        Self { addr, creds, rooms: Vec::new() }
+    }
+
+    /// 要加入的房间（不存在则创建）
+    pub fn room(mut self, room: impl Into<String>, pwd: impl Into<String>) -> Self {
+        self.rooms.push((room.into(), pwd.into()));
+        self
+    }
+
+    /// 一直运行，直到口令或房间被拒绝、或者被踢出所有房间
+    ///
+    /// 运行中的断线由客户端自动重连并重新进房；服务器暂时连不上时按 1、2、4…30 秒退避重试。
+    pub async fn run(self, bot: &mut impl Bot) -> Result<()> {
+        if self.rooms.is_empty() {
+            bail!("no room to join");
+        }
+        let mut attempt = 0u32;
+        loop {
+            match self.session(bot).await {
+                // 连上过就重新计数
+                Ok(true) => attempt = 0,
+                Ok(false) => return Ok(()),
+                // 只有网络错误值得重试，口令或房间被拒绝重试也没用
+                Err(e) if e.downcast_ref::<std::io::Error>().is_none() => return Err(e),
+                Err(e) => eprintln!("bot: {e}"),
+            }
+            attempt += 1;
+            let delay = (1u64 << (attempt - 1).min(5)).min(MAX_BACKOFF);
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+        }
+    }
+
+    /// 一次连接；返回 `Ok(true)` 表示连接结束后应当重连
+    async fn session(&self, bot: &mut impl Bot) -> Result<bool> {
+        let (client, mut events) = Client::connect(self.addr.clone(), self.creds.clone()).await?;
+        for (room, pwd) in &self.rooms {
+            client.join(room, pwd).await?;
+        }
+        // 已进入的房间，全部被踢出或进不去时结束
+        let mut joined = HashSet::new();
+        while let Some(event) = events.next().await {
+            let room = match &event {
+                ClientEvent::Message { room, .. }
+                | ClientEvent::Image { room, .. }
+                | ClientEvent::MemberList { room, .. }
+                | ClientEvent::UserJoined { room, .. }
+                | ClientEvent::UserLeft { room, .. }
+                | ClientEvent::Joined { room }
+                | ClientEvent::JoinFailed { room, .. }
+                | ClientEvent::Kicked { room, .. }
+                | ClientEvent::Topic { room, .. } => room.as_str(),
+                ClientEvent::Error { room, .. } | ClientEvent::Notice { room, .. } => room.as_deref().unwrap_or(""),
+                _ => "",
+            };
+            let mut ctx = Context::new(room, client.nickname());
+            dispatch(bot, &mut ctx, &event);
+            for (room, text) in ctx.outbox {
+                client.send_text(&room, &text).await?;
+            }
+            match event {
+                ClientEvent::Joined { room } => {
+                    joined.insert(room);
+                }
+                ClientEvent::Kicked { room, .. } | ClientEvent::JoinFailed { room, .. } => {
+                    joined.remove(&room);
+                    if joined.is_empty() {
+                        let _ = client.quit().await;
+                        return Ok(false);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn dispatch(bot: &mut impl Bot, ctx: &mut Context, event: &ClientEvent) {
+    match event {
+        ClientEvent::Message { sender, text, history: false, .. } => {
+            if *sender == ctx.me {
+                return;
+            }
+            match parse_command(bot.prefix(), text) {
+                Some(cmd) if cmd.name == "help" && !bot.commands().iter().any(|(usage, _)| usage.split_whitespace().next() == Some("help")) => {
+                    let prefix = bot.prefix();
+                    let list: Vec<String> = bot.commands().iter().map(|(usage, about)| format!("{prefix}{usage} — {about}")).collect();
+                    ctx.reply(if list.is_empty() { "no commands".to_string() } else { list.join(" | ") });
+                }
+                Some(cmd) => bot.on_command(ctx, sender, &cmd),
+                None => bot.on_message(ctx, sender, text),
+            }
+        }
+        ClientEvent::Message { .. } => {}
+        ClientEvent::Image { sender, history, .. } if *history || *sender == ctx.me => {}
+        ClientEvent::UserJoined { nick, .. } if *nick != ctx.me => bot.on_join(ctx, nick),
+        ClientEvent::UserLeft { nick, .. } if *nick != ctx.me => bot.on_leave(ctx, nick),
+        _ => bot.on_event(ctx, event),
+    }
+}
@@ -0,0 +1,266 @@
+// client/cli.rs
+//! 非交互模式：`client send` / `client listen`，给脚本、定时任务和 CI 用
+//!
+//! 口令不从命令行读取（会出现在进程列表里），只从环境变量或文件读取：
+//! 服务器口令 `CHAT_SERVER_PASSWORD` / `--server-password-file`，
+//! 房间密码 `CHAT_ROOM_PASSWORD` / `--room-password-file`（都不设为空密码）。
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use clap::{Args, ValueEnum};
+use futures_util::StreamExt;
+use serde_json::json;
+
+use super::addr::ServerAddr;
+use super::headless::{Client, ClientEvent, Credentials, Events};
+
+/// 不用 `RUST_CHAT_` 前缀：那是服务器配置的环境变量覆盖，同一个 shell 里起服务器会被当成未知配置项
+pub const SERVER_PASSWORD_ENV: &str = "CHAT_SERVER_PASSWORD";
+pub const ROOM_PASSWORD_ENV: &str = "CHAT_ROOM_PASSWORD";
+
+/// 握手与等待回显的超时
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 进程退出码；参数错误由 clap 以 2 退出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Ok = 0,
+    /// 其他错误（读口令文件失败、图片过大等）
+    Failure = 1,
+    /// 连不上服务器，或连接中途断开且无法恢复
+    Connect = 3,
+    /// 服务器拒绝认证（口令错误、限速、锁定）
+    Auth = 4,
+    /// 进不了房间，或被踢出 / 封禁
+    Room = 5,
+    /// 消息被服务器拒绝（禁言、过大等）
+    Rejected = 6,
+    /// 等待服务器超时
+    Timeout = 7,
+}
+
+/// 两个子命令共用的连接参数
+#[derive(Args, Debug)]
+pub struct ConnectArgs {
+    /// 服务器地址，如 chat.example.lan:6655
+    #[arg(long)]
+    pub server: ServerAddr,
+    #[arg(long)]
+    pub room: String,
+    /// 昵称，默认取配置文件中的 nickname
+    #[arg(long)]
+    pub nick: Option<String>,
+    /// 服务器口令文件（否则读 CHAT_SERVER_PASSWORD）
+    #[arg(long)]
+    pub server_password_file: Option<PathBuf>,
+    /// 房间密码文件（否则读 CHAT_ROOM_PASSWORD，都不设为空密码）
+    #[arg(long)]
+    pub room_password_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct SendArgs {
+    #[command(flatten)]
+    pub conn: ConnectArgs,
+    /// 要发送的文本
+    #[arg(long, required_unless_present = "image", conflicts_with = "image")]
+    pub message: Option<String>,
+    /// 要发送的图片（PNG/JPEG）
+    #[arg(long)]
+    pub image: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// `[时间] [房间] 昵称: 内容`
+    Text,
+    /// 每个事件一行 JSON
+    Jsonl,
+}
+
+#[derive(Args, Debug)]
+pub struct ListenArgs {
+    #[command(flatten)]
+    pub conn: ConnectArgs,
+    #[arg(long, value_enum, default_value = "text")]
+    pub format: Format,
+    /// 收到这么多条消息（不含历史）后退出
+    #[arg(long)]
+    pub count: Option<usize>,
+    /// 不输出进房时补发的历史
+    #[arg(long)]
+    pub no_history: bool,
+}
+
+/// 文件优先，其次环境变量
+fn secret(file: Option<&PathBuf>, env: &str) -> Result<Option<String>> {
+    if let Some(path) = file {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        return Ok(Some(text.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    Ok(std::env::var(env).ok())
+}
+
+/// 连接并进入房间；失败时给出对应的退出码
+async fn open(conn: &ConnectArgs) -> Result<(Client, Events), (Exit, anyhow::Error)> {
+    let failure = |e| (Exit::Failure, e);
+    let server_pwd = secret(conn.server_password_file.as_ref(), SERVER_PASSWORD_ENV)
+        .map_err(failure)?
+        .ok_or_else(|| failure(anyhow!("no server password: set {SERVER_PASSWORD_ENV} or pass --server-password-file")))?;
+    let room_pwd = secret(conn.room_password_file.as_ref(), ROOM_PASSWORD_ENV).map_err(failure)?.unwrap_or_default();
+    let nick = conn.nick.clone()
+        .or_else(|| super::config::current().nickname.clone())
+        .unwrap_or_else(|| "cli".to_string());
+    let (client, events) = tokio::time::timeout(TIMEOUT, Client::connect(conn.server.clone(), Credentials::new(server_pwd, nick)))
+        .await
+        .map_err(|_| (Exit::Timeout, anyhow!("handshake timed out")))?
+        .map_err(|e| {
+            // 连不上是 io 错误，其余是服务器拒绝认证
+            let code = if e.downcast_ref::<std::io::Error>().is_some() { Exit::Connect } else { Exit::Auth };
+            (code, e)
+        })?;
+    tokio::time::timeout(TIMEOUT, client.join(&conn.room, &room_pwd))
+        .await
+        .map_err(|_| (Exit::Timeout, anyhow!("joining {} timed out", conn.room)))?
+        .map_err(|e| (Exit::Room, e))?;
+    Ok((client, events))
+}
+
+/// 发送一条消息，等服务器回显确认送达后退出
+pub async fn send(args: SendArgs) -> Exit {
+    report(send_inner(args).await)
+}
+
+async fn send_inner(args: SendArgs) -> Result<(), (Exit, anyhow::Error)> {
+    let image = match &args.image {
+        Some(path) => Some(std::fs::read(path).map_err(|e| (Exit::Failure, anyhow!("{}: {e}", path.display())))?),
+        None => None,
+    };
+    let (client, mut events) = open(&args.conn).await?;
+    let room = args.conn.room.as_str();
+    let sent = match (&image, &args.message) {
+        (Some(data), _) => client.send_image(room, data).await,
+        (None, Some(text)) => client.send_text(room, text).await,
+        (None, None) => unreachable!("clap requires --message or --image"),
+    };
+    sent.map_err(|e| (Exit::Failure, e))?;
+    // 服务器把消息回显给发送者：看到自己的这条即已送达房间
+    let delivered = async {
+        while let Some(event) = events.next().await {
+            let me = client.nickname();
+            match event {
+                ClientEvent::Message { room: r, sender, text, history: false, .. }
+                    if r == room && sender == me && args.message.as_deref() == Some(text.as_str()) => return Ok(()),
+                ClientEvent::Image { room: r, sender, data, history: false, .. }
+                    if r == room && sender == me && image.as_deref() == Some(data.as_slice()) => return Ok(()),
+                ClientEvent::Error { room: Some(r), why } if r == room => return Err((Exit::Rejected, anyhow!("{why}"))),
+                ClientEvent::Kicked { room: r, by, .. } if r == room => return Err((Exit::Room, anyhow!("kicked by {by}"))),
+                ClientEvent::Disconnected => return Err((Exit::Connect, anyhow!("connection lost"))),
+                _ => {}
+            }
+        }
+        Err((Exit::Connect, anyhow!("connection closed")))
+    };
+    let result = tokio::time::timeout(TIMEOUT, delivered)
+        .await
+        .unwrap_or_else(|_| Err((Exit::Timeout, anyhow!("no confirmation from the server"))));
+    let _ = client.quit().await;
+    result
+}
+
+/// 输出房间里的消息，直到连接结束、被踢出或收满 `--count` 条；断线会自动重连
+pub async fn listen(args: ListenArgs) -> Exit {
+    report(listen_inner(args).await)
+}
+
+async fn listen_inner(args: ListenArgs) -> Result<(), (Exit, anyhow::Error)> {
+    let (client, mut events) = open(&args.conn).await?;
+    let mut received = 0;
+    while let Some(event) = events.next().await {
+        let history = matches!(event, ClientEvent::Message { history: true, .. } | ClientEvent::Image { history: true, .. });
+        if history && args.no_history {
+            continue;
+        }
+        let line = match args.format {
+            Format::Text => text_line(&event),
+            Format::Jsonl => json_line(&event),
+        };
+        if let Some(line) = line {
+            println!("{line}");
+        }
+        match event {
+            ClientEvent::Message { history: false, .. } | ClientEvent::Image { history: false, .. } => {
+                received += 1;
+                if args.count.is_some_and(|n| received >= n) {
+                    let _ = client.quit().await;
+                    return Ok(());
+                }
+            }
+            ClientEvent::Kicked { by, ban, .. } => {
+                let verb = if ban { "banned" } else { "kicked" };
+                return Err((Exit::Room, anyhow!("{verb} by {by}")));
+            }
+            _ => {}
+        }
+    }
+    Err((Exit::Connect, anyhow!("connection closed")))
+}
+
+fn report(result: Result<(), (Exit, anyhow::Error)>) -> Exit {
+    match result {
+        Ok(()) => Exit::Ok,
+        Err((code, e)) => {
+            eprintln!("error: {e:#}");
+            code
+        }
+    }
+}
+
+fn hms(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// 文本格式只输出聊天内容与房间动态
+fn text_line(event: &ClientEvent) -> Option<String> {
+    Some(match event {
+        ClientEvent::Message { room, sender, text, ts, .. } => format!("[{}] [{room}] {sender}: {text}", hms(*ts)),
+        ClientEvent::Image { room, sender, data, ts, .. } => {
+            format!("[{}] [{room}] {sender}: <image, {} bytes>", hms(*ts), data.len())
+        }
+        ClientEvent::UserJoined { room, nick } => format!("[{room}] {nick} joined"),
+        ClientEvent::UserLeft { room, nick } => format!("[{room}] {nick} left"),
+        ClientEvent::Topic { room, by: Some(by), topic } => {
+            format!("[{room}] {by} set the topic: {}", topic.as_deref().unwrap_or(""))
+        }
+        ClientEvent::Notice { text, .. } => format!("* {text}"),
+        ClientEvent::Error { why, .. } => format!("! {why}"),
+        _ => return None,
+    })
+}
+
+fn json_line(event: &ClientEvent) -> Option<String> {
+    let value = match event {
+        ClientEvent::Message { room, sender, text, ts, history } => {
+            json!({ "type": "message", "room": room, "sender": sender, "text": text, "ts": ts, "history": history })
+        }
+        ClientEvent::Image { room, sender, data, ts, history } => json!({
+            "type": "image", "room": room, "sender": sender, "ts": ts, "history": history,
+            "data": general_purpose::STANDARD.encode(data),
+        }),
+        ClientEvent::MemberList { room, members } => json!({ "type": "members", "room": room, "members": members }),
+        ClientEvent::UserJoined { room, nick } => json!({ "type": "join", "room": room, "nick": nick }),
+        ClientEvent::UserLeft { room, nick } => json!({ "type": "leave", "room": room, "nick": nick }),
+        ClientEvent::Kicked { room, by, ban } => json!({ "type": "kicked", "room": room, "by": by, "ban": ban }),
+        ClientEvent::Error { room, why } => json!({ "type": "error", "room": room, "why": why }),
+        ClientEvent::Topic { room, by, topic } => json!({ "type": "topic", "room": room, "by": by, "topic": topic }),
+        ClientEvent::Notice { room, text } => json!({ "type": "notice", "room": room, "text": text }),
+        ClientEvent::Shutdown { secs } => json!({ "type": "shutdown", "secs": secs }),
+        ClientEvent::Disconnected => json!({ "type": "disconnected" }),
+        ClientEvent::Reconnected => json!({ "type": "reconnected" }),
+        _ => return None,
+    };
+    Some(value.to_string())
+}
@@ -0,0 +1,159 @@
+// client/config.rs
+//! 客户端持久化配置：`<XDG 配置目录>/rust_chat/config.toml`
+//!
+//! 保存服务器地址簿、默认昵称/房间、界面配色、提醒偏好与图片大小上限。
+//! 服务器密码只以 `vault_seal`（主口令加密）后的形式落盘。
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{RwLock, RwLockReadGuard},
+};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+
+/// 地址簿中的一条服务器记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerEntry {
+    pub name: String,
+    pub addr: String,
+    /// 主口令加密后的服务器密码（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// 聊天界面配色：`#rrggbb` 或颜色名（red / blue / green …）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    pub border: String,
+    pub own:    String,
+    pub other:  String,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            border: "#008700".into(),
+            own:    "blue".into(),
+            other:  "red".into(),
+        }
+    }
+}
+
+/// 新消息提醒
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// 总开关
+    pub enabled: bool,
+    /// 提示音（Linux/macOS 为终端响铃）
+    pub sound:   bool,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self { enabled: true, sound: true }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub nickname:        Option<String>,
+    pub room:            Option<String>,
+    /// 聊天中用 `/join` 新建的房间不出现在服务器房间列表里
+    pub hide_new_rooms:  bool,
+    /// 收发图片的最大字节数
+    pub max_image_bytes: usize,
+    /// 接收的单帧（一行）最大字节数，超出即断开
+    pub max_frame_bytes: usize,
+    pub ui:              UiConfig,
+    pub notify:          NotifyConfig,
+    #[serde(rename = "server")]
+    pub servers:         Vec<ServerEntry>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            nickname:        None,
+            room:            None,
+            hide_new_rooms:  false,
+            max_image_bytes: 8 * 1024 * 1024,
+            max_frame_bytes: super::frame::DEFAULT_MAX_FRAME,
+            ui:              UiConfig::default(),
+            notify:          NotifyConfig::default(),
+            servers:         Vec::new(),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn find_server(&self, addr: &str) -> Option<&ServerEntry> {
+        self.servers.iter().find(|s| s.addr == addr)
+    }
+}
+
+static CONFIG: Lazy<RwLock<ClientConfig>> = Lazy::new(|| RwLock::new(ClientConfig::default()));
+
+/// 配置文件路径
+pub fn path() -> Result<PathBuf> {
+    let dir = dirs::config_dir().ok_or_else(|| anyhow!("no config directory on this platform"))?;
+    Ok(dir.join("rust_chat").join("config.toml"))
+}
+
+/// 启动时调用：读取配置文件到全局；文件不存在则沿用默认值
+pub fn load() -> Result<()> {
+    let path = path()?;
+    let cfg = match fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).map_err(|e| anyhow!("{}: {e}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => ClientConfig::default(),
+        Err(e) => return Err(e.into()),
+    };
+    *CONFIG.write().unwrap() = cfg;
+    Ok(())
+}
+
+/// 当前配置（只读）
+pub fn current() -> RwLockReadGuard<'static, ClientConfig> {
+    CONFIG.read().unwrap()
+}
+
+/// 修改配置并立即写回磁盘
+pub fn update(f: impl FnOnce(&mut ClientConfig)) -> Result<()> {
+    let mut cfg = CONFIG.write().unwrap();
+    f(&mut cfg);
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, toml::to_string_pretty(&*cfg)?)?;
+    Ok(())
+}
+
+static IDENTITY: OnceCell<[u8; 32]> = OnceCell::new();
+
+/// 本机身份密钥（`<配置目录>/rust_chat/identity`），首次使用时随机生成
+///
+/// 服务器按它派生的标识记录房主、管理员与封禁；读写失败时退化为本次运行有效的随机值。
+pub fn identity_secret() -> [u8; 32] {
+    *IDENTITY.get_or_init(|| {
+        let path = path().ok().map(|p| p.with_file_name("identity"));
+        if let Some(Ok(text)) = path.as_ref().map(fs::read_to_string) {
+            if let Ok(bytes) = hex::decode(text.trim()) {
+                if let Ok(secret) = <[u8; 32]>::try_from(bytes) {
+                    return secret;
+                }
+            }
+        }
+        let mut secret = [0u8; 32];
+        rand::RngCore::fill_bytes(&mut rand::rng(), &mut secret);
+        if let Some(path) = path {
+            let _ = path.parent().map(fs::create_dir_all);
+            let _ = fs::write(&path, hex::encode(secret));
+        }
+        secret
+    })
+}
@@ -5,26 +5,50 @@ use base64::{engine::general_purpose as b64, Engine};
 use chacha20::{cipher::{KeyIvInit, StreamCipher}, ChaCha20};
 use rand::RngCore;
 // ----------------- 常量 -----------------
-static mut ROOM_KEY: [u8; 32] = [0u8; 32]; // 自行替换
 static mut SERVER_KEY: [u8; 32] = [0u8; 32]; // 自行替换
-pub fn set_room_key(md5_hex: &str) {
-    let mut buf = [0u8; 16];
-    hex::decode_to_slice(md5_hex, &mut buf).expect("md5 hex len != 32");
-    unsafe {
-        ROOM_KEY[..16].copy_from_slice(&buf);
-        ROOM_KEY[16..].copy_from_slice(&buf);
-    }
+
+/// 房间密钥：md5(room_id + pwd) 重复两次铺满 32 字节
+pub type RoomKey = [u8; 32];
+
+/// 由房间号和密码得到 (房间密钥, 提交给服务器的凭据)
+pub fn room_secrets(room_id: &str, pwd: &str) -> (RoomKey, String) {
+    use md5::{Digest, Md5};
+    use hmac::{Hmac, Mac};
+    let digest = Md5::digest(format!("{room_id}{pwd}").as_bytes()); // 16 B
+    let mut key = [0u8; 32];
+    key[..16].copy_from_slice(&digest);
+    key[16..].copy_from_slice(&digest);
+    // 用 md5 把 “Hello” 包装成 MAC，作为凭据
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&digest).unwrap();
+    mac.update(b"Hello");
+    (key, hex::encode(mac.finalize().into_bytes()))
+}
+/// 哈希模式服务器上代替房间名的房间号：HMAC(房间密钥, "room-id")
+///
+/// 房间密钥由房间名和密码派生、从不发给服务器，所以服务器无法拿猜测的房间名去比对；
+/// 代价是密码输错时会进入另一个（新的）房间而不是被拒绝。
+pub fn room_hash(room_key: &RoomKey) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(room_key).unwrap();
+    mac.update(b"room-id");
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 发给服务器的身份标识：HMAC(本机身份密钥, 服务器身份盐)
+///
+/// 每个服务器的盐不同，看到的值也不同；盐与服务器口令无关，轮换口令后身份不变。
+/// 旧服务器不下发盐时以服务器密钥代替。
+pub fn server_identity(secret: &[u8; 32], salt: &[u8]) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).unwrap();
+    mac.update(salt);
+    hex::encode(mac.finalize().into_bytes())
 }
 pub fn set_server_key(md5_hex: [u8; 32]) {
     unsafe {
         SERVER_KEY[..].copy_from_slice(&md5_hex);
     }
 }
-// 2) 把内部所有加解密改成使用 ROOM_KEY
-fn current_key() -> &'static [u8; 32] {
-    let ptr: *const [u8; 32] = &raw const ROOM_KEY;
-    unsafe { &*ptr }
-}
 fn current_server_key() -> &'static [u8; 32] {
     let ptr: *const [u8; 32] = &raw const SERVER_KEY;
     unsafe { &*ptr }
@@ -38,6 +62,15 @@ const NONCE_LEN: usize = 12;   // ChaCha20-Poly1305 = 96-bit
 const KEY_LEN: usize  = 32;    // 256-bit
 
 pub fn server_seal(plain: String) -> String {
+    aead_seal(current_server_key(), &plain)
+}
+
+pub fn server_open(line: &str) -> Option<String> {
+    aead_open(current_server_key(), line)
+}
+
+/// ChaCha20-Poly1305 + HKDF 随机盐，输出 Base64(salt|nonce|ciphertext_and_tag)
+pub fn aead_seal(root: &[u8; 32], plain: &str) -> String {
     // 1. 随机 salt + nonce
     let mut salt  = [0u8; SALT_LEN];
     let mut nonce = [0u8; NONCE_LEN];
@@ -45,7 +78,7 @@ pub fn server_seal(plain: String) -> String {
     rand::rng().fill_bytes(&mut nonce);
 
     // 2. HKDF(SHA-256) 派生一次性密钥
-    let hk = Hkdf::<Sha256>::new(Some(&salt), current_server_key().as_ref());
+    let hk = Hkdf::<Sha256>::new(Some(&salt), root.as_ref());
     let mut key = [0u8; KEY_LEN];
     hk.expand(b"enc", &mut key).unwrap();
 
@@ -63,7 +96,7 @@ pub fn server_seal(plain: String) -> String {
     b64::STANDARD.encode(out)
 }
 
-pub fn server_open(line: &str) -> Option<String> {
+pub fn aead_open(root: &[u8; 32], line: &str) -> Option<String> {
     let decoded = b64::STANDARD.decode(line).ok()?;
     if decoded.len() < SALT_LEN + NONCE_LEN + 16 { return None; } // “16”是 Poly1305 tag
 
@@ -72,7 +105,7 @@ pub fn server_open(line: &str) -> Option<String> {
     let (nonce, ct)    = rest.split_at(NONCE_LEN);
 
     // 2. 派生同样的会话密钥
-    let hk = Hkdf::<Sha256>::new(Some(salt), current_server_key().as_ref());
+    let hk = Hkdf::<Sha256>::new(Some(salt), root.as_ref());
     let mut key = [0u8; KEY_LEN];
     hk.expand(b"enc", &mut key).ok()?;
 
@@ -81,13 +114,23 @@ pub fn server_open(line: &str) -> Option<String> {
     let plain  = cipher.decrypt(Nonce::from_slice(nonce), ct).ok()?;
     String::from_utf8(plain).ok()
 }
-pub fn seal(plain: &str) -> String {
+
+/// 本地保险箱：用主口令加密要落盘的服务器密码
+pub fn vault_seal(master: &str, plain: &str) -> String {
+    aead_seal(&pwd_hash(master), plain)
+}
+
+/// 主口令错误或密文被篡改时返回 None
+pub fn vault_open(master: &str, sealed: &str) -> Option<String> {
+    aead_open(&pwd_hash(master), sealed)
+}
+pub fn seal(key: &RoomKey, plain: &str) -> String {
     // 生成随机 12 字节 nonce（IV）
     let mut iv = [0u8; 12];
     rand::rng().fill_bytes(&mut iv);
     // 加密
     let mut data = plain.as_bytes().to_vec();
-    ChaCha20::new(current_key().into(), &iv.into()).apply_keystream(&mut data);
+    ChaCha20::new(key.into(), &iv.into()).apply_keystream(&mut data);
 
     // 拼装：ENC:<base64(iv + cipher)>
     let mut iv_cipher = iv.to_vec();
@@ -95,16 +138,16 @@ pub fn seal(plain: &str) -> String {
     format!("ENC:{}", b64::STANDARD.encode(iv_cipher))
 }
 
-pub fn open(line: &str) -> Option<String> {
+pub fn open(key: &RoomKey, line: &str) -> Option<String> {
     // 非密文行直接返回 None
-    let Some(encoded) = line.strip_prefix("ENC:") else { return None };
+    let encoded = line.strip_prefix("ENC:")?;
 
     let decoded = b64::STANDARD.decode(encoded).ok()?;
     if decoded.len() < 12 { return None; }
     let (iv, cipher) = decoded.split_at(12);
 
     let mut plain = cipher.to_vec();
-    ChaCha20::new(current_key().into(), iv.into()).apply_keystream(&mut plain);
+    ChaCha20::new(key.into(), iv.into()).apply_keystream(&mut plain);
     String::from_utf8(plain).ok()
 }
 use sha2::{Digest as ShaDigest, Sha256};
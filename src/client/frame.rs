@@ -0,0 +1,74 @@
+// client/frame.rs
+//! 有长度上限的按行读取：代替 `BufReader::lines()`，超长的行直接报错而不是无限缓冲
+//!
+//! 服务器和客户端共用；服务器在 ROOMS 横幅里用 `+max_frame=<字节>` 告知自己的上限。
+use std::io;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// 默认单帧上限：8 MiB 图片经 base64 与两层加密后约 15 MiB，留出余量
+pub const DEFAULT_MAX_FRAME: usize = 24 * 1024 * 1024;
+
+/// 超长帧的错误信息（`io::ErrorKind::InvalidData`）
+pub const FRAME_TOO_LARGE: &str = "FrameTooLarge";
+
+pub struct FrameReader<R> {
+    inner: BufReader<R>,
+    buf:   Vec<u8>,
+    max:   usize,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(inner: R, max: usize) -> Self {
+        FrameReader { inner: BufReader::new(inner), buf: Vec::new(), max }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn set_max(&mut self, max: usize) {
+        self.max = max;
+    }
+
+    /// 读一行（不含换行符），对端关闭返回 `None`
+    ///
+    /// 可以放进 `tokio::select!`：读到一半被取消时，已读部分留在内部缓冲里。
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let available = self.inner.fill_buf().await?;
+            if available.is_empty() {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return self.take_line().map(Some);
+            }
+            let (chunk, done) = match available.iter().position(|&b| b == b'\n') {
+                Some(i) => (i, true),
+                None => (available.len(), false),
+            };
+            if self.buf.len() + chunk > self.max {
+                self.buf.clear();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, FRAME_TOO_LARGE));
+            }
+            self.buf.extend_from_slice(&available[..chunk]);
+            self.inner.consume(if done { chunk + 1 } else { chunk });
+            if done {
+                return self.take_line().map(Some);
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> io::Result<String> {
+        let mut bytes = std::mem::take(&mut self.buf);
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// 判断是不是 [`FrameReader`] 的超长错误
+pub fn is_too_large(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::InvalidData && e.to_string() == FRAME_TOO_LARGE
+}
@@ -1,131 +1,212 @@
 // client/handshake.rs
 use anyhow::{anyhow, Result};
-use md5::{Digest, Md5};
 use rpassword::read_password;
 use std::io::{self, Write};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
-    net::TcpStream,
+    io::AsyncWriteExt,
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
 };
-use super::utils::{parse_invitation,handshake_writeall_macro};
+use super::utils::{parse_invitation, seal_line};
 use super::crypto;
+use super::addr::ServerAddr;
 use colored::*;
-use hmac::{Hmac, Mac};
-use sha2::Sha256;
 use rand::{distr::Alphanumeric, Rng};
-use super::crypto::{server_open,enc_auth};
-/// 返回已经握手成功、可以直接进入聊天循环的
-/// `(Lines<OwnedReadHalf>, OwnedWriteHalf, String /*room_id*/)`
-pub async fn connect_and_login(
-    server_addr_or_invite: &str,
-    nickname: &str,
-) -> Result<(Lines<BufReader<tokio::net::tcp::OwnedReadHalf>>,
-            tokio::net::tcp::OwnedWriteHalf,
-            String,String)> {
-            if server_addr_or_invite.starts_with("/INVITE:") {
-                // 1) 解码
-                let (server_addr,enc_pwd, room_id, pwd) = match parse_invitation(server_addr_or_invite) {
-                    Some(t) => t,
-                    None => {
-                        return Err(anyhow!("Invalid or expired invitation"));
-                    }
-                };
-                set_server_key(enc_pwd);
-                use super::crypto::chacha_once;
-                let auth = chacha_once(b"OKYOUARECORRECT", &enc_pwd);
-                // 2) 先连 TCP
-                let stream = TcpStream::connect(&server_addr).await?;
-                let (reader, mut writer) = stream.into_split();
-                let mut lines = BufReader::new(reader).lines();
-                let auth = {
-                    // enc_pwd1 是 Base64(layer-1)
-                    use super::crypto::{period_key};
-                    use chrono::Utc;
-                    use base64::Engine;
-                    // 再包第二层
-                    let outer = {
-                        let now = Utc::now().timestamp();
-                        super::crypto::chacha_once(&auth, &period_key(now))
-                    };
-                    base64::engine::general_purpose::STANDARD.encode(outer)
-                };
-                let cipher = handshake_writeall_macro(format!("AUTH {auth}"));
-                writer.write_all(&cipher).await?;
-                // 等待 OK
-                let resp = lines.next_line().await?
-                    .ok_or_else(|| anyhow!("Server closed during auth or {:?}",lines))?;
-                if server_open(&resp).ok_or_else(|| anyhow!("{}",resp))?.trim() != "OK" {
-                    return Err(anyhow!("Server declined: {}", resp));
-                }
-
-
-                // 与原流程相同：读取 "ROOMS ..." 横幅
-                let first = lines.next_line().await?
-                    .ok_or_else(|| anyhow!("server closed during handshake"))?;
-                let first = server_open(&first).unwrap_or(first);
-                if !first.starts_with("ROOMS") {
-                    return Err(anyhow!("unexpected banner: {}", first));
-                }
-        
-                // 3) 直接拼 JOIN 指令，无需交互
-                let digest = Md5::digest(format!("{room_id}{pwd}"));
-                crypto::set_room_key(&hex::encode(digest));
-                let mut mac = Hmac::<Sha256>::new_from_slice(&digest).unwrap();
-                mac.update(b"Hello");
-                let credential = hex::encode(mac.finalize().into_bytes());
-                let cmd = handshake_writeall_macro(format!("JOIN {room_id} {credential} {nickname}"));
-                writer.write_all(&cmd).await?;
-                // 4) 等待服务器 OK
-                let resp = lines.next_line().await?
-                    .ok_or_else(|| anyhow!("Server closed during handshake-2"))?;
-                let resp = server_open(&resp).unwrap_or(resp);
-                if resp.trim() != "OK" {
-                    return Err(anyhow!("Server refused: {}", resp));
-                }
-                return Ok((lines, writer, room_id,pwd));
-            }
-    // 0. TCP 连接
+use super::crypto::{aead_open, enc_auth, pwd_hash};
+use super::frame::FrameReader;
 
+pub type Reader = FrameReader<OwnedReadHalf>;
 
-    let mut iter = server_addr_or_invite.splitn(2, '&');
-    let server = iter.next().unwrap_or("");
-    let password = iter.next().unwrap_or("");
+/// 握手成功、可以直接进入聊天循环的连接
+pub struct Login {
+    pub lines:      Reader,
+    pub writer:     OwnedWriteHalf,
+    /// 本连接的服务器层密钥（sha256(服务器密码)）
+    pub server_key: [u8; 32],
+    pub room_id:    String,
+    pub pwd:        String,
+    /// 请求的昵称；服务器若改了名会随后发 `/nick`
+    pub nickname:   String,
+    /// 服务器只接受房间号哈希（ROOMS 横幅带 `+hashed`）
+    pub hashed:     bool,
+    /// 服务器接受的单帧上限（ROOMS 横幅的 `+max_frame=`），旧服务器不告知
+    pub max_frame:  Option<usize>,
+    /// 握手用的身份密钥，断线重连时沿用
+    pub identity:   [u8; 32],
+}
+
+/// 已通过 AUTH、尚未进房的连接
+pub(super) struct Session {
+    lines:  Reader,
+    writer: OwnedWriteHalf,
+    key:       [u8; 32],
+    hashed:    bool,
+    max_frame: Option<usize>,
+    /// 本机身份密钥，进房时派生出发给服务器的身份标识
+    identity:  [u8; 32],
+    /// 服务器身份盐（ROOMS 横幅的 `+salt=`），旧服务器不下发
+    salt:      Option<Vec<u8>>,
+}
+
+impl Session {
+    /// 改用指定的身份密钥（同一台机器上跑多个机器人时各用各的）
+    pub(super) fn with_identity(mut self, identity: [u8; 32]) -> Self {
+        self.identity = identity;
+        self
+    }
+}
 
-    let stream = TcpStream::connect(server).await?;
+async fn send(writer: &mut OwnedWriteHalf, key: &[u8; 32], line: String) -> Result<()> {
+    writer.write_all(&seal_line(key, line)).await?;
+    Ok(())
+}
+
+/// 读一行并尝试用服务器密钥解密；ERR 等明文原样返回
+async fn recv(lines: &mut Reader, key: &[u8; 32], stage: &str) -> Result<String> {
+    let line = lines.next_line().await?
+        .ok_or_else(|| anyhow!("Server closed during {stage}"))?;
+    Ok(aead_open(key, &line).unwrap_or(line))
+}
+
+/// TCP 连接 + AUTH + 读取 "ROOMS ..." 横幅，返回服务器公开的房间
+///
+/// 横幅里以 `+` 开头的是服务器能力标记而不是房间名。
+pub(super) async fn authenticate(
+    addr: &ServerAddr,
+    key: &[u8; 32],
+    auth: String,
+) -> Result<(Session, Vec<String>)> {
+    let stream = addr.connect().await?;
     let (reader, mut writer) = stream.into_split();
-    let mut lines = BufReader::new(reader).lines();
-    let auth = enc_auth(password);
-    use super::crypto::{set_server_key,pwd_hash};
-    set_server_key(pwd_hash(password));
+    let mut lines = FrameReader::new(reader, super::config::current().max_frame_bytes);
 
-    let cipher = handshake_writeall_macro(format!("AUTH {auth}"));
-    writer.write_all(&cipher).await?;
+    send(&mut writer, key, format!("AUTH {auth}")).await?;
     // 等待 OK
-    let resp = lines.next_line().await?
-        .ok_or_else(|| anyhow!("Server closed during auth or {:?}",lines))?;
-    if server_open(&resp).ok_or_else(|| anyhow!("{}",resp))?.trim() != "OK" {
+    let resp = recv(&mut lines, key, "auth").await?;
+    if resp.trim() != "OK" {
         return Err(anyhow!("Server declined: {}", resp));
     }
 
-    // 1. 服务器首条消息：房间列表
-    let first = lines
-        .next_line()
-        .await?
-        .ok_or_else(|| anyhow!("server closed during handshake"))?;
-    let first = server_open(&first).unwrap_or(first);
+    // 服务器首条消息：房间列表
+    let first = recv(&mut lines, key, "handshake").await?;
     if !first.starts_with("ROOMS") {
         return Err(anyhow!("unexpected banner: {}", first));
     }
-    let rooms: Vec<String> = first.split_whitespace().skip(1).map(|s| s.to_owned()).collect();
-    if rooms.is_empty() {
+    let (flags, rooms): (Vec<&str>, Vec<&str>) = first.split_whitespace().skip(1).partition(|s| s.starts_with('+'));
+    let max_frame = flags.iter().find_map(|f| f.strip_prefix("+max_frame=")?.parse().ok());
+    let salt = flags.iter().find_map(|f| hex::decode(f.strip_prefix("+salt=")?).ok());
+    let identity = super::config::identity_secret();
+    let session = Session { lines, writer, key: *key, hashed: flags.contains(&"+hashed"), max_frame, identity, salt };
+    Ok((session, rooms.into_iter().map(str::to_owned).collect()))
+}
+
+/// 发送 <ACTION> <ROOM> <CRED> <NICK> <IDENTITY> [hidden] 并等待服务器 OK
+pub(super) async fn enter(
+    session: Session,
+    action: &str,
+    room_id: String,
+    pwd: String,
+    nickname: &str,
+    hidden: bool,
+) -> Result<Login> {
+    let Session { mut lines, mut writer, key, hashed, max_frame, identity: secret, salt } = session;
+    // 计算 md5，作为房间密钥 & 凭据（密钥由调用方按房间保存）
+    let (room_key, credential) = crypto::room_secrets(&room_id, &pwd);
+    // 第 5 段：身份标识，服务器据此判断房主/管理员与封禁
+    let identity = crypto::server_identity(&secret, salt.as_deref().unwrap_or(&key));
+    // 哈希模式下看不到房间列表，无法区分创建还是加入
+    let (action, wire) = if hashed {
+        (if action == "CREATE" { "ENTER" } else { action }, crypto::room_hash(&room_key))
+    } else {
+        (action, room_id.clone())
+    };
+    let mut cmd = format!("{action} {wire} {credential} {nickname} {identity}");
+    if hidden {
+        cmd.push_str(" hidden");
+    }
+    send(&mut writer, &key, cmd).await?;
+    let resp = recv(&mut lines, &key, "handshake-2").await?;
+    if resp.trim() != "OK" {
+        return Err(anyhow!("Server refused: {}", resp));
+    }
+    Ok(Login { lines, writer, server_key: key, room_id, pwd, nickname: nickname.to_string(), hashed, max_frame, identity: secret })
+}
+
+/// 邀请码：服务器密码的哈希随邀请码下发，直接 JOIN，无需交互
+async fn login_with_invite(invite: &str, nickname: &str) -> Result<Login> {
+    let (server_addr, enc_pwd, room_id, pwd) = parse_invitation(invite)
+        .ok_or_else(|| anyhow!("Invalid or expired invitation"))?;
+    let auth = {
+        // 先包 layer-1，再用时间片密钥包第二层
+        use super::crypto::{chacha_once, period_key};
+        use chrono::Utc;
+        use base64::Engine;
+        let inner = chacha_once(b"OKYOUARECORRECT", &enc_pwd);
+        let outer = chacha_once(&inner, &period_key(Utc::now().timestamp()));
+        base64::engine::general_purpose::STANDARD.encode(outer)
+    };
+    let (session, _) = authenticate(&server_addr, &enc_pwd, auth).await?;
+    enter(session, "JOIN", room_id, pwd, nickname, false).await
+}
+
+/// 非交互登录：已知服务器、房间与密码（UI 内 /connect 使用）
+pub async fn connect_direct(
+    addr: &ServerAddr,
+    server_pwd: &str,
+    room_id: &str,
+    pwd: &str,
+    nickname: &str,
+) -> Result<Login> {
+    connect_as(addr, server_pwd, room_id, pwd, nickname, super::config::identity_secret()).await
+}
+
+/// 同 [`connect_direct`]，但用指定的身份密钥（重连时沿用原连接的身份）
+pub(super) async fn connect_as(
+    addr: &ServerAddr,
+    server_pwd: &str,
+    room_id: &str,
+    pwd: &str,
+    nickname: &str,
+    identity: [u8; 32],
+) -> Result<Login> {
+    let key = pwd_hash(server_pwd);
+    let (session, rooms) = authenticate(addr, &key, enc_auth(server_pwd)).await?;
+    let session = session.with_identity(identity);
+    // 不在列表里可能是隐藏房间：ENTER 存在则加入，否则创建
+    let action = if rooms.iter().any(|r| r == room_id) { "JOIN" } else { "ENTER" };
+    let hidden = super::config::current().hide_new_rooms;
+    enter(session, action, room_id.to_string(), pwd.to_string(), nickname, hidden).await
+}
+
+/// 交互式登录：`addr&pwd` 或 `/INVITE:…`，房间号和密码从终端读取
+pub async fn connect_and_login(
+    server_addr_or_invite: &str,
+    nickname: &str,
+) -> Result<Login> {
+    if server_addr_or_invite.starts_with("/INVITE:") {
+        return login_with_invite(server_addr_or_invite, nickname).await;
+    }
+
+    // 0. TCP 连接 + AUTH
+    let mut iter = server_addr_or_invite.splitn(2, '&');
+    let server = iter.next().unwrap_or("");
+    let password = iter.next().unwrap_or("");
+    let server: ServerAddr = server.parse()?;
+    let key = pwd_hash(password);
+    let (session, rooms) = authenticate(&server, &key, enc_auth(password)).await?;
+
+    // 1. 服务器首条消息：房间列表
+    if session.hashed {
+        println!("\n{}","— Private server: rooms are not listed —".green().bold());
+    } else if rooms.is_empty() {
         println!("\n{}","— No Rooms Available —".green().bold());
     } else {
         println!("\n{} \n {}","— Available Rooms —".green().bold(), rooms.join("; "));
     }
 
     // 2. 本地交互：输入房间号 & 密码
-    let (room_id, pwd, action) = loop {
-        print!("{}","Enter \"/q\" to disconnect, leave blank to join the Public Room,".yellow().bold());
+    let default_room = super::config::current().room.clone().unwrap_or_else(|| "Public".to_string());
+    let (room_id, pwd, action, hidden) = 'input: {
+        print!("{}",format!("Enter \"/q\" to disconnect, leave blank to join the {default_room} Room,").yellow().bold());
         print!("{}","Room ID: ".blue());
         io::stdout().flush()?;
         let mut id = String::new();
@@ -151,47 +232,33 @@ pub async fn connect_and_login(
                     CHARSET[idx] as char
                 })
                 .collect();
-            break (room_id, pwd, "CREATE");
+            // 随机房间总是隐藏
+            break 'input (room_id, pwd, "CREATE", true);
         }
 
-        let id = if id.trim().is_empty() {"Public"} else {id.trim()} ;
+        let id = if id.trim().is_empty() {default_room.as_str()} else {id.trim()} ;
         if id != "Public" {
             print!("{}","It wouldn't display while typing,".yellow().bold());
             print!("{}","Password:".red());
             io::stdout().flush()?;
             let pwd = read_password()?;
-            let act = if rooms.contains(&id.to_string()) { "JOIN" } else { "CREATE" };
-            break (id.to_owned(), pwd, act);
+            if rooms.contains(&id.to_string()) {
+                break 'input (id.to_owned(), pwd, "JOIN", false);
+            }
+            // 列表里没有：可能是隐藏房间（ENTER 加入），也可能要新建
+            let hidden = !session.hashed && {
+                print!("{}","Not listed. Hide it from the room list if it gets created? [y/N] ".yellow());
+                io::stdout().flush()?;
+                super::initialization::read_yes()?
+            };
+            break 'input (id.to_owned(), pwd, "ENTER", hidden);
         } else {
         let pwd = String::from("");
         let act = if rooms.contains(&id.to_string()) { "JOIN" } else { "CREATE" };
-        break (id.to_owned(), pwd, act);
+        break 'input (id.to_owned(), pwd, act, false);
         }
-        
     };
 
-    // 3. 计算 md5，作为房间密钥 & 凭据
-    let digest = Md5::digest(format!("{room_id}{pwd}").as_bytes()); // 16 B
-    let md5_hex = hex::encode(digest);
-    // ① 把 md5 设置为本房间的会话密钥
-    crypto::set_room_key(&md5_hex);
-    // ② 用它把 “Hello” 包装成密文，作为凭据
-    let mut mac = Hmac::<Sha256>::new_from_slice(&digest).unwrap();
-    mac.update(b"Hello");
-    let tag = mac.finalize().into_bytes();
-    let credential = hex::encode(tag);
-
-    // 4. 发送指令：<ACTION> <ROOM> <CRED> <NICK>
-    let cmd = handshake_writeall_macro(format!("{action} {room_id} {credential} {nickname}"));
-    writer.write_all(&cmd).await?;
-    // 5. 等待握手结果
-    let resp = lines
-        .next_line()
-        .await?
-        .ok_or_else(|| anyhow!("server closed during handshake‑2"))?;
-    let resp = server_open(&resp).unwrap_or(resp);
-    if resp.trim() != "OK" {
-        return Err(anyhow!("server refused: {}", resp));
-    }
-    Ok((lines, writer, room_id,pwd))
+    // 3. 发送指令并等待握手结果
+    enter(session, action, room_id, pwd, nickname, hidden).await
 }
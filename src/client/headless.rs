@@ -0,0 +1,359 @@
+// client/headless.rs
+//! 无界面的客户端：给机器人、命令行工具和测试用
+//!
+//! [`Client::connect`] 完成 AUTH，[`Client::join`] 进入第一个房间时才真正开始收发；
+//! 之后的一切（消息、图片、成员列表、错误、断线重连）都以 [`ClientEvent`] 从 [`Events`] 流出。
+//! 收发与重连复用 TUI 的 [`network::run`]，重连后自动重新加入其余房间。
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose, Engine as _};
+use futures_util::Stream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender, WeakUnboundedSender};
+
+use super::addr::ServerAddr;
+use super::crypto::{enc_auth, open, pwd_hash, room_secrets, seal, RoomKey};
+use super::handshake::{self, Session};
+use super::network::{self, Incoming, Outgoing};
+use super::receiver::{moderation_text, settings_text};
+use super::utils::parse_text_img;
+
+/// 服务器口令与昵称
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub server_password: String,
+    pub nickname:        String,
+    /// 身份密钥；None 用本机的（配置目录下的 `identity` 文件）
+    pub identity:        Option<[u8; 32]>,
+}
+
+impl Credentials {
+    pub fn new(server_password: impl Into<String>, nickname: impl Into<String>) -> Self {
+        Self { server_password: server_password.into(), nickname: nickname.into(), identity: None }
+    }
+
+    /// 用独立的身份密钥：服务器据此区分房主、管理员与封禁
+    pub fn with_identity(mut self, identity: [u8; 32]) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+}
+
+/// 客户端收到的事件；房间内容已用房间密钥解密
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// 聊天消息（含服务器回显的自己的消息）；`history` 为进房时补发的历史，`ts` 为 Unix 秒
+    Message { room: String, sender: String, text: String, ts: i64, history: bool },
+    /// 图片（原始字节，通常是 PNG）
+    Image { room: String, sender: String, data: Vec<u8>, ts: i64, history: bool },
+    MemberList { room: String, members: Vec<String> },
+    UserJoined { room: String, nick: String },
+    UserLeft { room: String, nick: String },
+    Joined { room: String },
+    JoinFailed { room: String, why: String },
+    /// 服务器确认的本连接昵称
+    Nick { nick: String },
+    /// 被踢出或封禁，已不在该房间
+    Kicked { room: String, by: String, ban: bool },
+    /// 服务器拒绝了某个操作（房间内的为 `/mod_err`，否则如改名失败）
+    Error { room: Option<String>, why: String },
+    /// 房间主题；`by` 为 None 表示进房时同步的现有主题，`topic` 为 None 表示已清除
+    Topic { room: String, by: Option<String>, topic: Option<String> },
+    /// 欢迎信息、公告、房间设置与管理事件等提示文本
+    Notice { room: Option<String>, text: String },
+    /// 服务器即将关停
+    Shutdown { secs: u64 },
+    /// 连接断开；可以重连时随后是 `Reconnecting`
+    Disconnected,
+    Reconnecting { attempt: u32, delay: u64 },
+    Reconnected,
+}
+
+/// 句柄与事件流共享的状态
+struct Shared {
+    /// 已加入（或正在加入）的房间 → (房间密钥, 凭据)：事件流据此解密，重连后据此重新加入
+    rooms:    Mutex<HashMap<String, (RoomKey, String)>>,
+    nickname: Mutex<String>,
+    /// 重连后重新加入房间用；弱引用，不阻止 Client 全部丢弃后断开
+    out_tx:   Mutex<Option<WeakUnboundedSender<Outgoing>>>,
+}
+
+enum State {
+    /// 已通过 AUTH，等待第一个房间
+    Pending { session: Box<Session>, listed: Vec<String>, net_tx: UnboundedSender<(usize, Incoming)> },
+    Running(UnboundedSender<Outgoing>),
+    /// 首次进房失败后连接已不可用
+    Failed,
+}
+
+/// 客户端句柄，可以 clone；全部丢弃后断开连接
+#[derive(Clone)]
+pub struct Client {
+    addr:   ServerAddr,
+    creds:  Credentials,
+    state:  Arc<tokio::sync::Mutex<State>>,
+    shared: Arc<Shared>,
+}
+
+impl Client {
+    /// 连接并通过 AUTH；返回的 [`Events`] 在连接结束后终止
+    pub async fn connect(addr: ServerAddr, creds: Credentials) -> Result<(Client, Events)> {
+        let key = pwd_hash(&creds.server_password);
+        let (session, listed) = handshake::authenticate(&addr, &key, enc_auth(&creds.server_password)).await?;
+        let session = match creds.identity {
+            Some(identity) => session.with_identity(identity),
+            None => session,
+        };
+        let (net_tx, net_rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            rooms:    Mutex::new(HashMap::new()),
+            nickname: Mutex::new(creds.nickname.clone()),
+            out_tx:   Mutex::new(None),
+        });
+        let client = Client {
+            addr,
+            creds,
+            state: Arc::new(tokio::sync::Mutex::new(State::Pending { session: Box::new(session), listed, net_tx })),
+            shared: shared.clone(),
+        };
+        let events = Events { net_rx, shared };
+        Ok((client, events))
+    }
+
+    /// 当前昵称（服务器可能自动加了后缀）
+    pub fn nickname(&self) -> String {
+        self.shared.nickname.lock().unwrap().clone()
+    }
+
+    /// 加入房间（不存在则创建）
+    ///
+    /// 第一个房间在握手里进入，被拒绝时直接返回错误；之后的房间结果以
+    /// `Joined` / `JoinFailed` 事件返回。
+    pub async fn join(&self, room: &str, pwd: &str) -> Result<()> {
+        let (key, credential) = room_secrets(room, pwd);
+        let mut state = self.state.lock().await;
+        match std::mem::replace(&mut *state, State::Failed) {
+            State::Pending { session, listed, net_tx } => {
+                // 不在列表里可能是隐藏房间：ENTER 存在则加入，否则创建
+                let action = if listed.iter().any(|r| r == room) { "JOIN" } else { "ENTER" };
+                let login = handshake::enter(*session, action, room.into(), pwd.into(), &self.creds.nickname, false).await?;
+                self.shared.rooms.lock().unwrap().insert(room.to_string(), (key, credential));
+                let (out_tx, out_rx) = mpsc::unbounded_channel();
+                *self.shared.out_tx.lock().unwrap() = Some(out_tx.downgrade());
+                net_tx.send((0, Incoming::Joined { room: room.to_string() })).ok();
+                let target = format!("{}&{}", self.addr, self.creds.server_password);
+                tokio::spawn(network::run(0, login, target, net_tx, out_rx));
+                *state = State::Running(out_tx);
+                Ok(())
+            }
+            State::Running(out_tx) => {
+                self.shared.rooms.lock().unwrap().insert(room.to_string(), (key, credential.clone()));
+                let sent = out_tx.send(Outgoing::Join { room: room.to_string(), credential, key, hidden: false });
+                *state = State::Running(out_tx);
+                sent.map_err(|_| anyhow!("connection closed"))
+            }
+            State::Failed => bail!("connection closed"),
+        }
+    }
+
+    pub async fn leave(&self, room: &str) -> Result<()> {
+        self.shared.rooms.lock().unwrap().remove(room);
+        self.send(Outgoing::Leave { room: room.to_string() }).await
+    }
+
+    pub async fn send_text(&self, room: &str, text: &str) -> Result<()> {
+        self.send(Outgoing::Plain { room: room.to_string(), text: text.to_string() }).await
+    }
+
+    /// 发送图片（PNG/JPEG 原始字节），大小受客户端配置的 `max_image_bytes` 限制
+    pub async fn send_image(&self, room: &str, data: &[u8]) -> Result<()> {
+        let limit = super::config::current().max_image_bytes;
+        if data.len() > limit {
+            bail!("image larger than {limit} bytes");
+        }
+        let text = format!("/IMGDATA{}", general_purpose::STANDARD.encode(data));
+        self.send(Outgoing::Plain { room: room.to_string(), text }).await
+    }
+
+    /// 设置房间主题（管理员），空字符串清除；主题用房间密钥加密，结果以 `Topic` / `Error` 事件返回
+    pub async fn set_topic(&self, room: &str, topic: &str) -> Result<()> {
+        let key = self.shared.rooms.lock().unwrap().get(room).map(|(key, _)| *key);
+        let key = key.ok_or_else(|| anyhow!("not in room {room}"))?;
+        let args = match topic.trim() {
+            "" => "topic".to_string(),
+            topic => format!("topic {}", seal(&key, topic)),
+        };
+        self.send(Outgoing::Configure { room: room.to_string(), args }).await
+    }
+
+    /// 房间管理：`kick bob`、`ban bob`、`mute bob 10m`、`unmute bob`、`op bob`；结果以 `Notice` / `Error` 事件返回
+    pub async fn moderate(&self, room: &str, cmd: &str) -> Result<()> {
+        self.send(Outgoing::Moderate { room: room.to_string(), cmd: cmd.to_string() }).await
+    }
+
+    /// 改昵称（本连接所有房间），结果以 `Nick` / `Error` 事件返回
+    pub async fn set_nick(&self, nick: &str) -> Result<()> {
+        self.send(Outgoing::Nick { nick: nick.to_string() }).await
+    }
+
+    /// 主动断开，不再重连
+    pub async fn quit(&self) -> Result<()> {
+        self.send(Outgoing::Quit).await
+    }
+
+    async fn send(&self, msg: Outgoing) -> Result<()> {
+        match &*self.state.lock().await {
+            State::Running(out_tx) => out_tx.send(msg).map_err(|_| anyhow!("connection closed")),
+            State::Pending { .. } => bail!("join a room first"),
+            State::Failed => bail!("connection closed"),
+        }
+    }
+}
+
+/// 客户端事件流
+pub struct Events {
+    net_rx: UnboundedReceiver<(usize, Incoming)>,
+    shared: Arc<Shared>,
+}
+
+impl Events {
+    fn handle(&self, msg: Incoming) -> Option<ClientEvent> {
+        let event = match msg {
+            Incoming::Room { room, line } => {
+                // 已离开的房间不再关心
+                let key = self.shared.rooms.lock().unwrap().get(&room).map(|(key, _)| *key)?;
+                let me = self.shared.nickname.lock().unwrap().clone();
+                let event = room_event(room, &line, &key, &me);
+                // 被踢出的房间也要忘掉，免得重连时 rejoin 又进去
+                if let Some(ClientEvent::Kicked { room, .. }) = &event {
+                    self.shared.rooms.lock().unwrap().remove(room);
+                }
+                return event;
+            }
+            Incoming::Joined { room } => ClientEvent::Joined { room },
+            Incoming::JoinFailed { room, why } => {
+                self.shared.rooms.lock().unwrap().remove(&room);
+                ClientEvent::JoinFailed { room, why }
+            }
+            Incoming::Nick { nick } => {
+                *self.shared.nickname.lock().unwrap() = nick.clone();
+                ClientEvent::Nick { nick }
+            }
+            Incoming::NickFailed { why } => ClientEvent::Error { room: None, why },
+            Incoming::Motd { line } => ClientEvent::Notice { room: None, text: line },
+            Incoming::Announce { text } => ClientEvent::Notice { room: None, text: format!("[server] {text}") },
+            Incoming::Shutdown { secs } => ClientEvent::Shutdown { secs },
+            Incoming::Closed => ClientEvent::Disconnected,
+            Incoming::Reconnecting { attempt, delay } => ClientEvent::Reconnecting { attempt, delay },
+            Incoming::Reconnected { room, nickname } => {
+                self.rejoin(&room, &nickname);
+                ClientEvent::Reconnected
+            }
+        };
+        Some(event)
+    }
+
+    /// 重连只进了登录时的房间：其余房间重新加入，登录房间已离开的退出，昵称改回来
+    fn rejoin(&self, login_room: &str, requested_nick: &str) {
+        let out_tx = self.shared.out_tx.lock().unwrap().as_ref().and_then(WeakUnboundedSender::upgrade);
+        let Some(out_tx) = out_tx else { return };
+        let rooms = self.shared.rooms.lock().unwrap().clone();
+        for (room, (key, credential)) in &rooms {
+            if room != login_room {
+                let (credential, key) = (credential.clone(), *key);
+                let _ = out_tx.send(Outgoing::Join { room: room.clone(), credential, key, hidden: false });
+            }
+        }
+        if !rooms.contains_key(login_room) {
+            let _ = out_tx.send(Outgoing::Leave { room: login_room.to_string() });
+        }
+        let nick = self.shared.nickname.lock().unwrap().clone();
+        if nick != requested_nick {
+            let _ = out_tx.send(Outgoing::Nick { nick });
+        }
+    }
+}
+
+impl Stream for Events {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ClientEvent>> {
+        // 不关心的网络消息跳过，继续取下一条
+        loop {
+            match self.net_rx.poll_recv(cx) {
+                Poll::Ready(Some((_, msg))) => {
+                    if let Some(event) = self.handle(msg) {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// 房间内的一行（服务器层已解密）→ 事件；不关心的控制帧返回 None
+fn room_event(room: String, line: &str, key: &RoomKey, me: &str) -> Option<ClientEvent> {
+    if let Some(list) = line.strip_prefix("/member_list ") {
+        let members = list.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect();
+        return Some(ClientEvent::MemberList { room, members });
+    }
+    // 历史回放：`/history <unix 秒> [nick] ENC:…`
+    if let Some(rest) = line.strip_prefix("/history ") {
+        let (ts, line) = rest.split_once(' ')?;
+        return chat_event(room, line, key, ts.parse().ok()?, true);
+    }
+    if let Some(why) = line.strip_prefix("/mod_err ") {
+        return Some(ClientEvent::Error { room: Some(room), why: why.to_string() });
+    }
+    if let Some(rest) = line.strip_prefix("/kicked ") {
+        let (by, kind) = rest.split_once(' ').unwrap_or((rest, "kick"));
+        return Some(ClientEvent::Kicked { room, by: by.to_string(), ban: kind == "ban" });
+    }
+    if let Some(missed) = line.strip_prefix("/lagged ") {
+        let text = format!("connection too slow: {missed} message(s) were dropped by the server");
+        return Some(ClientEvent::Notice { room: Some(room), text });
+    }
+    if let Some((old, new)) = line.strip_prefix("/renamed ").and_then(|r| r.split_once(' ')) {
+        return Some(ClientEvent::Notice { room: Some(room), text: format!("{old} is now known as {new}") });
+    }
+    if let Some(rest) = line.strip_prefix("/topic ") {
+        let (by, sealed) = rest.split_once(' ').unwrap_or((rest, ""));
+        let topic = (!sealed.is_empty()).then(|| open(key, sealed).unwrap_or_else(|| "<undecryptable>".into()));
+        let by = (by != "-").then(|| by.to_string());
+        return Some(ClientEvent::Topic { room, by, topic });
+    }
+    // 进出房间：`⚡ [nick] joined.` / `⚡ [nick] left.`
+    if let Some(rest) = line.strip_prefix("⚡ [") {
+        let (nick, what) = rest.split_once("] ")?;
+        let nick = nick.to_string();
+        return match what {
+            "joined." => Some(ClientEvent::UserJoined { room, nick }),
+            "left." => Some(ClientEvent::UserLeft { room, nick }),
+            _ => None,
+        };
+    }
+    if line.starts_with('/') {
+        let text = moderation_text(line, me).or_else(|| settings_text(line, key))?;
+        return Some(ClientEvent::Notice { room: Some(room), text });
+    }
+    chat_event(room, line, key, chrono::Utc::now().timestamp(), false)
+}
+
+/// `[nick] ENC:…` → 文本或图片消息
+fn chat_event(room: String, line: &str, key: &RoomKey, ts: i64, history: bool) -> Option<ClientEvent> {
+    let (sender, body) = parse_text_img(line, key);
+    match body.strip_prefix("/IMGDATA") {
+        Some(b64) => {
+            let data = general_purpose::STANDARD.decode(b64).ok()?;
+            Some(ClientEvent::Image { room, sender, data, ts, history })
+        }
+        None => Some(ClientEvent::Message { room, sender, text: body, ts, history }),
+    }
+}
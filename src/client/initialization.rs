@@ -5,6 +5,11 @@ use fake::faker::name::raw::*;
 use colored::*;
 use std::io::IsTerminal;
 use supports_color::{self,Stream as ColorStream};
+use once_cell::sync::OnceCell;
+use rpassword::read_password;
+use super::addr::{ServerAddr, DEFAULT_PORT};
+use super::config::{self, ClientConfig, ServerEntry};
+use super::crypto::{vault_open, vault_seal};
 pub fn init_color() {
     if std::env::var_os("NO_COLOR").is_some() {
         colored::control::set_override(false);
@@ -27,73 +32,158 @@ fn get_password_or_default()->String {
 }
 pub fn initial_name() -> io::Result<String> {
     // ---------- 询问昵称 ----------
-    let username = loop {
-        println!("{}","Continue with fake name".purple());
-        print!("{}","      Or customize here: ".purple());
-        io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let name = input.trim();
-        if !name.is_empty() {
-            break name.to_owned();
-        } else {
-            let name: String = FirstName(FR_FR).fake();
-            break name.to_owned()
-        }
+    let default_name = config::current().nickname.clone();
+    match &default_name {
+        Some(name) => println!("{} {}","Continue with".purple(),name.purple().bold()),
+        None       => println!("{}","Continue with fake name".purple()),
+    }
+    print!("{}","      Or customize here: ".purple());
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let name = input.trim();
+    let username = if !name.is_empty() {
+        name.to_owned()
+    } else if let Some(name) = default_name {
+        name
+    } else {
+        FirstName(FR_FR).fake()
     };
-    println!("{} {}","Enjoy youself, ".green(),username.clone().to_string().green());
+    println!("{} {}","Enjoy youself, ".green(),username.green());
     Ok(username)
 }
-pub fn initial_serveraddr() -> io::Result<String> {
 
-    let servers = vec![
-        ("Please config at /src/client/initialization::initial_serveraddr", "127.0.0.1:6655"),
-    ];
+/// 本次会话内缓存的主口令，避免反复输入
+static MASTER: OnceCell<String> = OnceCell::new();
+
+fn master_passphrase() -> io::Result<String> {
+    if let Some(m) = MASTER.get() {
+        return Ok(m.clone());
+    }
+    print!("{}","Master passphrase: ".red());
+    io::stdout().flush()?;
+    let m = read_password()?;
+    let _ = MASTER.set(m.clone());
+    Ok(m)
+}
+
+/// 本次启动时输入过的主口令（没输入过为 None）
+pub fn cached_master() -> Option<&'static str> {
+    MASTER.get().map(String::as_str)
+}
+
+/// `/connect <host>` 不带口令时取地址簿里保存的密码；界面里没法提示输入主口令，
+/// 只能用 `master`（本次启动时输入过的）解开，解不开就报错而不是把密文当口令
+pub fn saved_server_password(cfg: &ClientConfig, host: &str, addr: &ServerAddr, master: Option<&str>) -> anyhow::Result<String> {
+    let sealed = cfg.find_server(host)
+        .or_else(|| cfg.find_server(&addr.to_string()))
+        .and_then(|s| s.password.as_deref())
+        .ok_or_else(|| anyhow::anyhow!("no saved password for {addr}"))?;
+    let master = master.ok_or_else(|| anyhow::anyhow!("the saved password for {addr} is locked (no master passphrase entered this session)"))?;
+    vault_open(master, sealed).ok_or_else(|| anyhow::anyhow!("cannot decrypt the saved password for {addr} with this session's master passphrase"))
+}
+
+/// 已保存的密码能解开就直接用，否则回退到手动输入
+fn saved_or_ask_password(entry: &ServerEntry) -> io::Result<String> {
+    if let Some(sealed) = &entry.password {
+        if let Some(pwd) = vault_open(&master_passphrase()?, sealed) {
+            return Ok(pwd);
+        }
+        println!("{}","Wrong master passphrase, enter the server password manually.".yellow());
+    }
+    print!("Server Password: ");
+    io::stdout().flush()?;
+    Ok(get_password_or_default())
+}
+
+pub fn initial_serveraddr() -> io::Result<String> {
+    let mut servers = config::current().servers.clone();
+    if servers.is_empty() {
+        servers.push(ServerEntry {
+            name:     "Local (add servers in config.toml)".into(),
+            addr:     format!("127.0.0.1:{DEFAULT_PORT}"),
+            password: None,
+        });
+    }
 
     // 交互循环直到拿到合法输入
     let chosen = loop {
         println!("\nAvaliable Servers:");
-        for (i, (name, _)) in servers.iter().enumerate() {
-            println!("  {}. {}", i + 1, name);
+        for (i, s) in servers.iter().enumerate() {
+            println!("  {}. {} ({})", i + 1, s.name, s.addr);
         }
-        print!("Choice / IP:Port / /INVITE:…  ➜ ");
+        print!("Choice / Host[:Port] / /INVITE:…  ➜ ");
         io::stdout().flush()?;
 
         let mut inp = String::new();
         io::stdin().read_line(&mut inp)?;
         let s = inp.trim();
         if s.is_empty() {
-            println!("Default Choice : {}", servers[0].0);
-            print!("Server Password: ");
-            io::stdout().flush()?;
-            let key = get_password_or_default();
-            break format!("{}&{}",servers[0].1.to_string(),key);
+            println!("Default Choice : {}", servers[0].name);
+            let key = saved_or_ask_password(&servers[0])?;
+            break format!("{}&{}", servers[0].addr, key);
         }
         // 1️⃣ 数字
         if let Ok(idx) = s.parse::<usize>() {
             if (1..=servers.len()).contains(&idx) {
-                print!("Server Password: ");
-                io::stdout().flush()?;
-                let key = get_password_or_default();
-                break format!("{}&{}",servers[idx - 1].1.to_string(),key.to_string());
+                let key = saved_or_ask_password(&servers[idx - 1])?;
+                break format!("{}&{}", servers[idx - 1].addr, key);
             }
         }
-        // 2️⃣ IP:Port  (简单正则校验 0-255.0-255.0-255.0-255:数字)
-        if regex::Regex::new(r"^(?:\d{1,3}\.){3}\d{1,3}:\d+$")
-            .unwrap()
-            .is_match(s)
-        {
-            print!("Server Password: ");
-            io::stdout().flush()?;
-            let key = get_password_or_default();
-            break format!("{}&{}",s.to_string(),key.to_string());
-        }
-        // 3️⃣ 邀请码
+        // 2️⃣ 邀请码
         if s.starts_with("/INVITE:") {
             break s.to_string();
         }
-        println!("Enter an choice, IP or invite code!");
+        // 3️⃣ 域名 / IPv4 / [IPv6]，端口可省略
+        match s.parse::<ServerAddr>() {
+            Ok(addr) => {
+                print!("Server Password: ");
+                io::stdout().flush()?;
+                let key = get_password_or_default();
+                break format!("{}&{}", addr, key);
+            }
+            Err(e) => println!("{e}. Enter a choice, host[:port] or invite code!"),
+        }
     };
 
     Ok(chosen)
 }
+
+/// 握手成功后询问是否把新服务器加入地址簿（邀请码与已保存的地址跳过）
+pub fn offer_save_server(server_addr: &str) -> io::Result<()> {
+    let Some((addr, pwd)) = server_addr.split_once('&') else { return Ok(()) };
+    if config::current().find_server(addr).is_some() {
+        return Ok(());
+    }
+    print!("{}","Save this server to the address book? [y/N] ".yellow());
+    io::stdout().flush()?;
+    if !read_yes()? {
+        return Ok(());
+    }
+    print!("Name [{}]: ", addr);
+    io::stdout().flush()?;
+    let mut name = String::new();
+    io::stdin().read_line(&mut name)?;
+    let name = if name.trim().is_empty() { addr.to_string() } else { name.trim().to_string() };
+
+    print!("{}","Store the password encrypted under a master passphrase? [y/N] ".yellow());
+    io::stdout().flush()?;
+    let password = if read_yes()? {
+        Some(vault_seal(&master_passphrase()?, pwd))
+    } else {
+        None
+    };
+
+    let entry = ServerEntry { name, addr: addr.to_string(), password };
+    match config::update(|c| c.servers.push(entry)) {
+        Ok(())  => println!("{}","Server saved.".green()),
+        Err(e)  => eprintln!("⚠️ Failed to save config: {e}"),
+    }
+    Ok(())
+}
+
+pub fn read_yes() -> io::Result<bool> {
+    let mut inp = String::new();
+    io::stdin().read_line(&mut inp)?;
+    Ok(matches!(inp.trim(), "y" | "Y" | "yes"))
+}
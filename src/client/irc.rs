@@ -0,0 +1,549 @@
+// client/irc.rs
+//! IRC 网关：让普通 IRC 客户端参与加密房间
+//!
+//! 每个 IRC 连接对应一个无界面客户端会话，房间密钥加解密都在网关里完成，
+//! 聊天服务器看到的仍是密文。频道 `#ops` 对应房间 `ops`，`JOIN #ops <密码>` 带房间密码；
+//! 服务器口令取 `PASS`，没有则用网关启动时给的默认口令。
+//!
+//! 支持 NICK、USER、PASS、JOIN、PART、PRIVMSG、NOTICE、NAMES、TOPIC、PING、QUIT，
+//! 以及客户端常发的 CAP、MODE、WHO（只回最小应答）。不支持私聊。
+use std::{collections::HashMap, net::SocketAddr};
+
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+};
+
+use super::addr::ServerAddr;
+use super::frame::FrameReader;
+use super::headless::{Client, ClientEvent, Credentials, Events};
+
+pub const DEFAULT_IRC_BIND: &str = "127.0.0.1:6667";
+pub const DEFAULT_IRC_PORT: u16 = 6667;
+
+/// 网关在 IRC 里的服务器名，也用作用户的主机名
+const HOST: &str = "rust_chat";
+/// 收到的一行上限（IRCv3 消息标签最长 8191 字节）
+const MAX_LINE: usize = 8192;
+/// 发给 IRC 客户端的消息正文按字节切分，留出前缀与频道名的余量
+const MAX_TEXT: usize = 400;
+
+pub struct Gateway {
+    listener: TcpListener,
+    server:   ServerAddr,
+    password: Option<String>,
+}
+
+impl Gateway {
+    /// `password` 为 IRC 客户端不发 `PASS` 时使用的服务器口令
+    pub async fn bind(bind: &str, server: ServerAddr, password: Option<String>) -> Result<Gateway> {
+        let addr = ServerAddr::parse_with_port(bind, DEFAULT_IRC_PORT)?;
+        let listener = addr.bind().await.map_err(|e| anyhow!("irc {addr}: {e}"))?;
+        Ok(Gateway { listener, server, password })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub async fn run(self) -> Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept().await?;
+            let (server, password) = (self.server.clone(), self.password.clone());
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, server, password).await {
+                    eprintln!("irc {peer}: {e}");
+                }
+            });
+        }
+    }
+}
+
+/// 一条 IRC 消息：`[:prefix] COMMAND param… [:trailing]`，命令转为大写
+fn parse_line(line: &str) -> Option<(String, Vec<String>)> {
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+    // IRCv3 标签与前缀都忽略
+    for marker in ['@', ':'] {
+        if rest.starts_with(marker) {
+            rest = rest.split_once(' ').map_or("", |(_, r)| r);
+        }
+    }
+    let (head, trailing) = match rest.split_once(" :") {
+        Some((head, trailing)) => (head, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = head.split_whitespace();
+    let command = words.next()?.to_ascii_uppercase();
+    let mut params: Vec<String> = words.map(str::to_string).collect();
+    params.extend(trailing.map(str::to_string));
+    Some((command, params))
+}
+
+/// 频道名 ↔ 房间名
+fn room_of(channel: &str) -> Option<&str> {
+    channel.strip_prefix('#').filter(|r| !r.is_empty())
+}
+
+fn channel_of(room: &str) -> String {
+    format!("#{room}")
+}
+
+/// 按行、按长度切分正文，去掉 IRC 里不能出现的控制字符
+fn chunks(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for line in text.lines() {
+        let line: String = line.chars().filter(|c| *c != '\0' && *c != '\r').collect();
+        let mut rest = line.as_str();
+        while rest.len() > MAX_TEXT {
+            let mut cut = MAX_TEXT;
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            out.push(rest[..cut].to_string());
+            rest = &rest[cut..];
+        }
+        out.push(rest.to_string());
+    }
+    out
+}
+
+#[derive(Default)]
+struct Channel {
+    members: Vec<String>,
+    topic:   Option<String>,
+    /// 进频道后第一次收到成员列表时回 NAMES，之后的变化靠 JOIN / PART 行
+    named:   bool,
+}
+
+/// 一个 IRC 连接的状态
+struct Conn {
+    writer:   OwnedWriteHalf,
+    server:   ServerAddr,
+    password: Option<String>,
+    nick:     Option<String>,
+    user:     bool,
+    pass:     Option<String>,
+    session:  Option<(Client, Events)>,
+    channels: HashMap<String, Channel>,
+    /// 待发给 IRC 客户端的行
+    out:      Vec<String>,
+}
+
+enum Input {
+    Line(std::io::Result<Option<String>>),
+    Event(Option<ClientEvent>),
+}
+
+async fn next_event(session: &mut Option<(Client, Events)>) -> Option<ClientEvent> {
+    match session {
+        Some((_, events)) => events.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn serve(stream: TcpStream, server: ServerAddr, password: Option<String>) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut lines = FrameReader::new(reader, MAX_LINE);
+    let mut conn = Conn {
+        writer,
+        server,
+        password,
+        nick: None,
+        user: false,
+        pass: None,
+        session: None,
+        channels: HashMap::new(),
+        out: Vec::new(),
+    };
+    loop {
+        let input = tokio::select! {
+            line = lines.next_line() => Input::Line(line),
+            event = next_event(&mut conn.session) => Input::Event(event),
+        };
+        let open = match input {
+            Input::Line(Ok(Some(line))) => conn.command(&line).await,
+            Input::Line(_) => false,
+            Input::Event(Some(event)) => {
+                conn.event(event);
+                true
+            }
+            Input::Event(None) => {
+                conn.out.push("ERROR :Connection to the chat server closed".into());
+                false
+            }
+        };
+        conn.flush().await?;
+        if !open {
+            break;
+        }
+    }
+    if let Some((client, _)) = &conn.session {
+        let _ = client.quit().await;
+    }
+    Ok(())
+}
+
+impl Conn {
+    fn me(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+
+    /// `:rust_chat <numeric> <nick> <params>`
+    fn reply(&mut self, numeric: &str, params: &str) {
+        let line = format!(":{HOST} {numeric} {} {params}", self.me());
+        self.out.push(line);
+    }
+
+    /// 以某个成员的身份发出的一行
+    fn from(&mut self, nick: &str, rest: &str) {
+        self.out.push(format!(":{nick}!{nick}@{HOST} {rest}"));
+    }
+
+    fn notice(&mut self, target: &str, text: &str) {
+        for chunk in chunks(text) {
+            self.out.push(format!(":{HOST} NOTICE {target} :{chunk}"));
+        }
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let mut buf = self.out.drain(..).collect::<Vec<_>>().join("\r\n");
+        buf.push_str("\r\n");
+        self.writer.write_all(buf.as_bytes()).await
+    }
+
+    fn client(&self) -> Option<&Client> {
+        self.session.as_ref().map(|(client, _)| client)
+    }
+
+    /// 处理一行命令；返回 false 表示关闭连接
+    async fn command(&mut self, line: &str) -> bool {
+        let Some((command, params)) = parse_line(line) else { return true };
+        let param = |i: usize| params.get(i).map(String::as_str);
+        if self.session.is_none() {
+            return match command.as_str() {
+                "CAP" if param(0) == Some("LS") => {
+                    self.out.push(format!(":{HOST} CAP * LS :"));
+                    true
+                }
+                "CAP" | "PONG" => true,
+                "PASS" => {
+                    self.pass = param(0).map(str::to_string);
+                    true
+                }
+                "NICK" => match param(0) {
+                    Some(nick) if valid_nick(nick) => {
+                        self.nick = Some(nick.to_string());
+                        self.register().await
+                    }
+                    _ => {
+                        self.reply("432", ":Erroneous nickname");
+                        true
+                    }
+                },
+                "USER" => {
+                    self.user = true;
+                    self.register().await
+                }
+                "PING" => {
+                    let token = param(0).unwrap_or(HOST).to_string();
+                    self.out.push(format!(":{HOST} PONG {HOST} :{token}"));
+                    true
+                }
+                "QUIT" => false,
+                _ => {
+                    self.reply("451", ":You have not registered");
+                    true
+                }
+            };
+        }
+        match command.as_str() {
+            "PING" => {
+                let token = param(0).unwrap_or(HOST).to_string();
+                self.out.push(format!(":{HOST} PONG {HOST} :{token}"));
+            }
+            "PONG" | "CAP" | "USER" | "PASS" => {}
+            "NICK" => match param(0) {
+                Some(nick) if valid_nick(nick) => {
+                    let Some(client) = self.client() else { return true };
+                    if let Err(e) = client.set_nick(nick).await {
+                        self.reply("400", &format!("NICK :{e}"));
+                    }
+                }
+                _ => self.reply("432", ":Erroneous nickname"),
+            },
+            "JOIN" => {
+                let Some(targets) = param(0) else { return self.need_more("JOIN") };
+                if targets == "0" {
+                    let joined: Vec<String> = self.channels.keys().cloned().collect();
+                    for room in joined {
+                        self.part(&room).await;
+                    }
+                    return true;
+                }
+                let keys: Vec<&str> = param(1).map(|k| k.split(',').collect()).unwrap_or_default();
+                for (i, channel) in targets.split(',').enumerate() {
+                    self.join(channel, keys.get(i).copied().unwrap_or("")).await;
+                }
+            }
+            "PART" => {
+                let Some(targets) = param(0) else { return self.need_more("PART") };
+                for channel in targets.split(',') {
+                    match room_of(channel).filter(|r| self.channels.contains_key(*r)) {
+                        Some(room) => self.part(room).await,
+                        None => self.reply("442", &format!("{channel} :You're not on that channel")),
+                    }
+                }
+            }
+            "PRIVMSG" | "NOTICE" => {
+                let (Some(target), Some(text)) = (param(0), param(1)) else { return self.need_more(&command) };
+                self.privmsg(&command, target, text).await;
+            }
+            "NAMES" => match param(0) {
+                Some(targets) => {
+                    for channel in targets.split(',') {
+                        self.names(channel);
+                    }
+                }
+                None => {
+                    let joined: Vec<String> = self.channels.keys().map(|r| channel_of(r)).collect();
+                    for channel in joined {
+                        self.names(&channel);
+                    }
+                }
+            },
+            "TOPIC" => {
+                let Some(channel) = param(0) else { return self.need_more("TOPIC") };
+                let Some(room) = room_of(channel).filter(|r| self.channels.contains_key(*r)) else {
+                    self.reply("442", &format!("{channel} :You're not on that channel"));
+                    return true;
+                };
+                match param(1) {
+                    Some(topic) => {
+                        if let Some(client) = self.client() {
+                            let _ = client.set_topic(room, topic).await;
+                        }
+                    }
+                    None => self.topic(channel),
+                }
+            }
+            "MODE" => match param(0) {
+                Some(channel) if channel.starts_with('#') => self.reply("324", &format!("{channel} +")),
+                Some(_) => self.reply("221", "+"),
+                None => return self.need_more("MODE"),
+            },
+            "WHO" => {
+                let mask = param(0).unwrap_or("*").to_string();
+                self.reply("315", &format!("{mask} :End of WHO list"));
+            }
+            "QUIT" => {
+                self.out.push(format!("ERROR :Closing link ({})", param(0).unwrap_or("Quit")));
+                return false;
+            }
+            _ => self.reply("421", &format!("{command} :Unknown command")),
+        }
+        true
+    }
+
+    fn need_more(&mut self, command: &str) -> bool {
+        self.reply("461", &format!("{command} :Not enough parameters"));
+        true
+    }
+
+    /// NICK 与 USER 都到齐后向聊天服务器认证
+    async fn register(&mut self) -> bool {
+        let Some(nick) = self.nick.clone().filter(|_| self.user) else { return true };
+        let Some(password) = self.pass.clone().or_else(|| self.password.clone()) else {
+            self.reply("464", ":Password required (PASS)");
+            self.out.push("ERROR :Password required".into());
+            return false;
+        };
+        match Client::connect(self.server.clone(), Credentials::new(password.clone(), nick.clone())).await {
+            Ok(session) => {
+                self.pass = Some(password);
+                self.session = Some(session);
+            }
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some() => {
+                self.out.push(format!("ERROR :Cannot reach the chat server: {e}"));
+                return false;
+            }
+            Err(e) => {
+                self.reply("464", ":Password incorrect");
+                self.out.push(format!("ERROR :{e}"));
+                return false;
+            }
+        }
+        self.reply("001", &format!(":Welcome to rust_chat via IRC, {nick}"));
+        self.reply("002", &format!(":Your host is {HOST}, the rust_chat IRC gateway"));
+        self.reply("003", ":Rooms are end-to-end encrypted; the gateway holds the room keys");
+        self.reply("004", &format!("{HOST} rust_chat-{} o o", env!("CARGO_PKG_VERSION")));
+        self.reply("005", "CHANTYPES=# PREFIX=() NICKLEN=32 :are supported by this server");
+        self.reply("422", ":MOTD is sent as notices");
+        true
+    }
+
+    async fn join(&mut self, channel: &str, key: &str) {
+        let Some(room) = room_of(channel) else {
+            self.reply("403", &format!("{channel} :No such channel"));
+            return;
+        };
+        if self.channels.contains_key(room) {
+            return;
+        }
+        let Some(client) = self.client() else { return };
+        // 成功与否以 Joined / JoinFailed 事件为准；第一个房间在握手里进入，失败直接返回
+        if let Err(e) = client.join(room, key).await {
+            self.reply("475", &format!("{channel} :Cannot join channel ({e})"));
+            // 第一个房间进不去时那条连接已作废，换一条新会话给之后的 JOIN 用
+            if self.channels.is_empty() {
+                let (nick, password) = (self.me().to_string(), self.pass.clone().unwrap_or_default());
+                match Client::connect(self.server.clone(), Credentials::new(password, nick)).await {
+                    Ok(session) => self.session = Some(session),
+                    Err(e) => self.out.push(format!("ERROR :{e}")),
+                }
+            }
+        }
+    }
+
+    async fn part(&mut self, room: &str) {
+        if let Some(client) = self.client() {
+            let _ = client.leave(room).await;
+        }
+        self.channels.remove(room);
+        let me = self.me().to_string();
+        self.from(&me, &format!("PART {}", channel_of(room)));
+    }
+
+    async fn privmsg(&mut self, command: &str, target: &str, text: &str) {
+        let Some(room) = room_of(target) else {
+            // 没有私聊；NOTICE 按规范不回错误
+            if command == "PRIVMSG" {
+                self.reply("401", &format!("{target} :Private messages are not supported"));
+            }
+            return;
+        };
+        if !self.channels.contains_key(room) {
+            if command == "PRIVMSG" {
+                self.reply("404", &format!("{target} :Cannot send to channel"));
+            }
+            return;
+        }
+        // CTCP ACTION（/me）转成普通文本
+        let text = match text.strip_prefix("\x01ACTION ") {
+            Some(action) => format!("* {} {}", self.me(), action.trim_end_matches('\x01')),
+            None => text.to_string(),
+        };
+        if let Some(client) = self.client() {
+            let _ = client.send_text(room, &text).await;
+        }
+    }
+
+    fn names(&mut self, channel: &str) {
+        let members = room_of(channel).and_then(|r| self.channels.get(r)).map(|c| c.members.join(" "));
+        if let Some(members) = members {
+            self.reply("353", &format!("= {channel} :{members}"));
+        }
+        self.reply("366", &format!("{channel} :End of /NAMES list"));
+    }
+
+    fn topic(&mut self, channel: &str) {
+        let topic = room_of(channel).and_then(|r| self.channels.get(r)).and_then(|c| c.topic.clone());
+        match topic {
+            Some(topic) => self.reply("332", &format!("{channel} :{topic}")),
+            None => self.reply("331", &format!("{channel} :No topic is set")),
+        }
+    }
+
+    /// 聊天事件 → IRC 行
+    fn event(&mut self, event: ClientEvent) {
+        let me = self.me().to_string();
+        match event {
+            ClientEvent::Message { room, sender, text, ts, history } => {
+                // 服务器回显的自己的消息，IRC 客户端已经显示过
+                if sender == me && !history {
+                    return;
+                }
+                let prefix = if history { history_stamp(ts) } else { String::new() };
+                for chunk in chunks(&text) {
+                    self.from(&sender, &format!("PRIVMSG {} :{prefix}{chunk}", channel_of(&room)));
+                }
+            }
+            ClientEvent::Image { room, sender, data, ts, history } => {
+                if sender == me && !history {
+                    return;
+                }
+                let prefix = if history { history_stamp(ts) } else { String::new() };
+                self.from(&sender, &format!("PRIVMSG {} :{prefix}<image, {} bytes>", channel_of(&room), data.len()));
+            }
+            ClientEvent::MemberList { room, members } => {
+                let Some(channel) = self.channels.get_mut(&room) else { return };
+                channel.members = members;
+                if !channel.named {
+                    channel.named = true;
+                    self.names(&channel_of(&room));
+                }
+            }
+            ClientEvent::UserJoined { room, nick } if nick != me => {
+                self.from(&nick, &format!("JOIN {}", channel_of(&room)));
+            }
+            ClientEvent::UserLeft { room, nick } if nick != me => {
+                self.from(&nick, &format!("PART {}", channel_of(&room)));
+            }
+            ClientEvent::UserJoined { .. } | ClientEvent::UserLeft { .. } => {}
+            ClientEvent::Joined { room } => {
+                if self.channels.contains_key(&room) {
+                    return;
+                }
+                self.channels.insert(room.clone(), Channel::default());
+                self.from(&me, &format!("JOIN {}", channel_of(&room)));
+            }
+            ClientEvent::JoinFailed { room, why } => {
+                self.reply("475", &format!("{} :Cannot join channel ({why})", channel_of(&room)));
+            }
+            ClientEvent::Nick { nick } => {
+                if nick != me {
+                    self.from(&me, &format!("NICK :{nick}"));
+                    self.nick = Some(nick);
+                }
+            }
+            ClientEvent::Kicked { room, by, ban } => {
+                self.channels.remove(&room);
+                let why = if ban { "banned" } else { "kicked" };
+                self.from(&by, &format!("KICK {} {me} :{why}", channel_of(&room)));
+            }
+            ClientEvent::Topic { room, by, topic } => {
+                let Some(channel) = self.channels.get_mut(&room) else { return };
+                channel.topic = topic.clone();
+                match by {
+                    Some(by) => self.from(&by, &format!("TOPIC {} :{}", channel_of(&room), topic.unwrap_or_default())),
+                    None => self.topic(&channel_of(&room)),
+                }
+            }
+            ClientEvent::Error { room, why } | ClientEvent::Notice { room, text: why } => {
+                let target = room.map_or(me, |r| channel_of(&r));
+                self.notice(&target, &why);
+            }
+            ClientEvent::Shutdown { secs } => self.notice(&me, &format!("The chat server shuts down in {secs}s")),
+            ClientEvent::Disconnected => self.notice(&me, "Lost the connection to the chat server"),
+            ClientEvent::Reconnecting { attempt, delay } => {
+                self.notice(&me, &format!("Reconnecting in {delay}s (attempt {attempt})"));
+            }
+            ClientEvent::Reconnected => self.notice(&me, "Reconnected to the chat server"),
+        }
+    }
+}
+
+fn valid_nick(nick: &str) -> bool {
+    !nick.is_empty() && nick.len() <= 32 && !nick.starts_with(['#', ':']) && !nick.contains([' ', ',', '!', '@'])
+}
+
+/// 历史消息在正文前标上时间
+fn history_stamp(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("[%H:%M] ").to_string())
+        .unwrap_or_default()
+}
@@ -1,45 +1,54 @@
 // client/keyboard.rs
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use tokio::sync::mpsc::UnboundedSender;
-use tui::widgets::ListState;
 use unicode_segmentation::UnicodeSegmentation;
 
 use super::receiver::ChatMessage;
 use super::clipboard::{self, ClipData};
 use super::utils::{parse_name_body, encode_rgba_as_png, HELP_TEXT,HELP_TEXT_EN, create_invitation};
+use super::addr::ServerAddr;
+use super::network::Outgoing;
+use super::crypto::seal;
+use super::tabs::{RoomTab, Tabs};
 use base64::Engine;
-pub enum ControlFlow { Continue, Quit }
+pub enum ControlFlow {
+    Continue,
+    /// 当前服务器的最后一个房间已关闭
+    Quit,
+    /// `/connect …`：由调用方握手并新增一个服务器连接
+    Connect(String),
+    NextServer,
+    PrevServer,
+}
 fn open_image(path: &std::path::Path) -> anyhow::Result<()> {
     open::that(path)?;
     Ok(())
 }
 /// 让 client 把所有可变状态打包进来，便于在这里直接修改。
-
 pub struct KeyCtx<'a> {
-    pub input:       &'a mut String,
-    pub cursor:      &'a mut usize,
-    pub list_state:  &'a mut ListState,
-    pub messages:    &'a mut Vec<ChatMessage>,
-    pub member_list: &'a mut Vec<String>, // 目前未用到，但保留以备扩展
-    pub undo_mgr:    &'a mut UndoMgr,
-    pub out_tx:      &'a UnboundedSender<String>,
-    pub server_addr: &'a mut String,
-    pub room_id:     &'a String,
-    pub pwd:         &'a String,
+    pub tabs:        &'a mut Tabs,
+    pub out_tx:      &'a UnboundedSender<Outgoing>,
+    pub server_addr: &'a str,
     pub username:    &'a String,
 }
 
-/// 处理一次 KeyEvent：改动都通过 ctx 传回；Esc 返回 Quit
+/// 处理一次 KeyEvent：改动都通过 ctx 传回；关闭最后一个房间时返回 Quit
 pub fn handle_key(key: KeyEvent, ctx: &mut KeyCtx) -> ControlFlow {
+    let out_tx = ctx.out_tx;
+    let room = ctx.tabs.current().room_id.clone();
+    let send = |text: String| {
+        let _ = out_tx.send(Outgoing::Text { room: room.clone(), text });
+    };
+    let tab = ctx.tabs.current_mut();
     match key.code {
         // =============== 剪贴板粘贴 ===============
         KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
             match clipboard::get() {
                 Ok(ClipData::Text(txt)) => {
-                    ctx.undo_mgr.maybe_push(ctx.input, *ctx.cursor, OpKind::Insert);
-                    let byte_idx = nth_grapheme_byte_idx(ctx.input, *ctx.cursor);
-                    ctx.input.insert_str(byte_idx, &txt);
-                    *ctx.cursor += txt.graphemes(true).count();
+                    tab.undo_mgr.maybe_push(&tab.input, tab.cursor, OpKind::Insert);
+                    let byte_idx = nth_grapheme_byte_idx(&tab.input, tab.cursor);
+                    tab.input.insert_str(byte_idx, &txt);
+                    tab.cursor += txt.graphemes(true).count();
                 }
                 Ok(ClipData::Image(img)) => {
                     let png_buf = match encode_rgba_as_png(&img.bytes,
@@ -47,23 +56,28 @@ pub fn handle_key(key: KeyEvent, ctx: &mut KeyCtx) -> ControlFlow {
                         img.height.try_into().unwrap()) {
                         Ok(b) => b,
                         Err(e) => {
-                            let _ = ctx.out_tx.send(format!("⚠️ Failed to encode image: {e}"));
+                            send(format!("⚠️ Failed to encode image: {e}"));
                             return ControlFlow::Continue;
                         }
                     };
+                    let limit = super::config::current().max_image_bytes;
+                    if png_buf.len() > limit {
+                        tab.push_system(&format!("⚠️ Image larger than {limit} bytes"));
+                        return ControlFlow::Continue;
+                    }
                     let b64 = base64::engine::general_purpose::STANDARD.encode(&png_buf);
-                    let _ = ctx.out_tx.send(format!("/IMGDATA{}", b64));
+                    send(format!("/IMGDATA{}", b64));
                 }
                 Err(e) => {
-                    let _ = ctx.out_tx.send(format!("⚠️ Failed to read clipboard: {e}"));
+                    send(format!("⚠️ Failed to read clipboard: {e}"));
                 }
             }
         }
 
         // =============== 复制选中行 ===============
         KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            if let Some(sel) = ctx.list_state.selected() {
-                let (_, _, body) = parse_name_body(&ctx.messages[sel]);
+            if let Some(sel) = tab.list_state.selected() {
+                let (_, _, body) = parse_name_body(&tab.messages[sel]);
                 if let Err(e) = clipboard::set_text(&body) {
                     eprintln!("⚠️ Failed to paste: {e}");
                 }
@@ -72,10 +86,10 @@ pub fn handle_key(key: KeyEvent, ctx: &mut KeyCtx) -> ControlFlow {
 
         // =============== 帮助文本 ===============
         KeyCode::Char('h') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            let _ = ctx.out_tx.send(HELP_TEXT.to_string());
+            send(HELP_TEXT.to_string());
         }
         KeyCode::Char('j') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            let _ = ctx.out_tx.send(HELP_TEXT_EN.to_string());
+            send(HELP_TEXT_EN.to_string());
         }
 
         // =============== 生成邀请码 ===============
@@ -83,102 +97,195 @@ pub fn handle_key(key: KeyEvent, ctx: &mut KeyCtx) -> ControlFlow {
             let mut iter = ctx.server_addr.splitn(2, '&');
             let server     = iter.next().unwrap_or("");
             let server_pwd = iter.next().unwrap_or("");
-            match create_invitation(server.to_string(), server_pwd.to_string(), ctx.room_id.clone(), ctx.pwd.clone()) {
-                Ok(code) => { let _ = ctx.out_tx.send(format!("/INVITE:{}", code)); }
-                Err(e)   => { let _ = ctx.out_tx.send("Failed to generate invite code".to_string()); eprintln!("Failed to generate invite code: {e}"); }
+            let invite = server.parse::<ServerAddr>()
+                .map_err(|e| e.to_string())
+                .and_then(|addr| create_invitation(addr, server_pwd.to_string(), tab.room_id.clone(), tab.pwd.clone())
+                    .map_err(|e| e.to_string()));
+            match invite {
+                Ok(code) => { send(format!("/INVITE:{}", code)); }
+                Err(e)   => { send("Failed to generate invite code".to_string()); eprintln!("Failed to generate invite code: {e}"); }
             }
         }
 
         // =============== 普通字符插入 ===============
         KeyCode::Char(ch) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
-            ctx.undo_mgr.maybe_push(ctx.input, *ctx.cursor, OpKind::Insert);
+            tab.undo_mgr.maybe_push(&tab.input, tab.cursor, OpKind::Insert);
             let s = ch.to_string();
-            let byte_idx = nth_grapheme_byte_idx(ctx.input, *ctx.cursor);
-            ctx.input.insert_str(byte_idx, &s);
-            *ctx.cursor += 1;
+            let byte_idx = nth_grapheme_byte_idx(&tab.input, tab.cursor);
+            tab.input.insert_str(byte_idx, &s);
+            tab.cursor += 1;
+        }
+
+        // =============== 标签切换 ===============
+        KeyCode::Left if key.modifiers.contains(KeyModifiers::ALT) => ctx.tabs.prev(),
+        KeyCode::Right if key.modifiers.contains(KeyModifiers::ALT) => ctx.tabs.next(),
+        KeyCode::Char(d @ '1'..='9') if key.modifiers.contains(KeyModifiers::ALT) => {
+            ctx.tabs.select(d as usize - '1' as usize);
         }
+        KeyCode::Up if key.modifiers.contains(KeyModifiers::ALT) => return ControlFlow::PrevServer,
+        KeyCode::Down if key.modifiers.contains(KeyModifiers::ALT) => return ControlFlow::NextServer,
 
         // =============== 光标移动 ===============
         KeyCode::Left  if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            *ctx.cursor = (*ctx.cursor).saturating_sub(3);
+            tab.cursor = tab.cursor.saturating_sub(3);
         }
-        KeyCode::Left  => { if *ctx.cursor > 0 { *ctx.cursor -= 1; } }
+        KeyCode::Left if tab.cursor > 0 => { tab.cursor -= 1; }
         KeyCode::Right if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            let total = ctx.input.graphemes(true).count();
-            if *ctx.cursor < total { *ctx.cursor = total; }
+            let total = tab.input.graphemes(true).count();
+            if tab.cursor < total { tab.cursor = total; }
         }
         KeyCode::Right => {
-            let total = ctx.input.graphemes(true).count();
-            if *ctx.cursor < total { *ctx.cursor += 1; }
+            let total = tab.input.graphemes(true).count();
+            if tab.cursor < total { tab.cursor += 1; }
         }
 
         // =============== Backspace / Enter / 清空 / 撤销 ===============
-        KeyCode::Backspace => {
-            if *ctx.cursor > 0 {
-                ctx.undo_mgr.maybe_push(ctx.input, *ctx.cursor, OpKind::Insert);
-                let start = nth_grapheme_byte_idx(ctx.input, *ctx.cursor - 1);
-                let end   = nth_grapheme_byte_idx(ctx.input, *ctx.cursor);
-                ctx.input.replace_range(start..end, "");
-                *ctx.cursor -= 1;
-            }
+        KeyCode::Backspace if tab.cursor > 0 => {
+            tab.undo_mgr.maybe_push(&tab.input, tab.cursor, OpKind::Insert);
+            let start = nth_grapheme_byte_idx(&tab.input, tab.cursor - 1);
+            let end   = nth_grapheme_byte_idx(&tab.input, tab.cursor);
+            tab.input.replace_range(start..end, "");
+            tab.cursor -= 1;
         }
         KeyCode::Enter => {
-            ctx.undo_mgr.maybe_push(ctx.input, *ctx.cursor, OpKind::Insert);
-            let msg = ctx.input.trim();
-            if !msg.is_empty() {
-                let _ = ctx.out_tx.send(msg.to_string());
-                ctx.input.clear();
-                *ctx.cursor = 0;
+            tab.undo_mgr.maybe_push(&tab.input, tab.cursor, OpKind::Insert);
+            let msg = tab.input.trim().to_string();
+            if msg.is_empty() {
+                return ControlFlow::Continue;
+            }
+            tab.input.clear();
+            tab.cursor = 0;
+            // 本地命令：/join <room> [pwd]、/leave
+            let cmd = msg.split_whitespace().next().unwrap_or_default();
+            if MOD_COMMANDS.contains(&cmd) {
+                let _ = ctx.out_tx.send(Outgoing::Moderate {
+                    room: ctx.tabs.current().room_id.clone(),
+                    cmd:  msg[1..].to_string(),
+                });
+            } else if let Some(args) = room_command(&msg, tab) {
+                let _ = ctx.out_tx.send(Outgoing::Configure { room: tab.room_id.clone(), args });
+            } else if let Some(nick) = msg.strip_prefix("/nick ") {
+                let _ = ctx.out_tx.send(Outgoing::Nick { nick: nick.trim().to_string() });
+            } else if let Some(args) = msg.strip_prefix("/join ") {
+                let mut it = args.split_whitespace();
+                if let Some(room_id) = it.next() {
+                    join_room(ctx, room_id, it.next().unwrap_or(""));
+                }
+            } else if msg == "/leave" {
+                return leave_current(ctx);
+            } else if let Some(target) = msg.strip_prefix("/connect ") {
+                return ControlFlow::Connect(target.trim().to_string());
+            } else {
+                send(msg);
             }
         }
         KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            ctx.undo_mgr.maybe_push(ctx.input, *ctx.cursor, OpKind::Insert);
-            ctx.input.clear();
-            *ctx.cursor = 0;
+            tab.undo_mgr.maybe_push(&tab.input, tab.cursor, OpKind::Insert);
+            tab.input.clear();
+            tab.cursor = 0;
         }
         KeyCode::Char('z') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            ctx.undo_mgr.undo(ctx.input, ctx.cursor);
+            tab.undo_mgr.undo(&mut tab.input, &mut tab.cursor);
         }
 
         // =============== 列表上下 & Tab 预览 ===============
         KeyCode::Up if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            if let Some(i) = ctx.list_state.selected() {
-                ctx.list_state.select(Some(i.saturating_sub(5)));
+            if let Some(i) = tab.list_state.selected() {
+                tab.list_state.select(Some(i.saturating_sub(5)));
             }
         }
         KeyCode::Up => {
-            if let Some(i) = ctx.list_state.selected() {
-                ctx.list_state.select(Some(i.saturating_sub(1)));
+            if let Some(i) = tab.list_state.selected() {
+                tab.list_state.select(Some(i.saturating_sub(1)));
             }
         }
         KeyCode::Down if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            ctx.list_state.select(Some(ctx.messages.len().saturating_sub(1)));
+            tab.list_state.select(Some(tab.messages.len().saturating_sub(1)));
         }
         KeyCode::Down => {
-            if let Some(i) = ctx.list_state.selected() {
-                let next = (i + 1).min(ctx.messages.len().saturating_sub(1));
-                ctx.list_state.select(Some(next));
+            if let Some(i) = tab.list_state.selected() {
+                let next = (i + 1).min(tab.messages.len().saturating_sub(1));
+                tab.list_state.select(Some(next));
             }
         }
         KeyCode::Tab => {
-            if let Some(sel) = ctx.list_state.selected() {
-                if let ChatMessage::Image { path, .. } = &ctx.messages[sel] {
+            if let Some(sel) = tab.list_state.selected() {
+                if let ChatMessage::Image { path, .. } = &tab.messages[sel] {
                     if let Err(e) = open_image(path) { eprintln!("Failed to open the image: {e}"); }
                 }
             }
         }
 
-        // =============== Esc 退出 ===============
-        KeyCode::Esc => {
-            let _ = ctx.out_tx.send("//~``~//".to_string());
-            return ControlFlow::Quit;
-        }
+        // =============== Esc 离开当前房间 ===============
+        KeyCode::Esc => return leave_current(ctx),
 
         _ => {}
     }
     ControlFlow::Continue
 }
 
+/// 交给服务器执行的管理命令
+const MOD_COMMANDS: [&str; 5] = ["/kick", "/ban", "/mute", "/unmute", "/op"];
+
+/// `/room …`、`/topic …`、`/pin …`、`/unpin [n]` → `/room` 参数
+///
+/// 主题与置顶（含 `/room topic|pin <文字>`）只以房间密钥密文交给服务器；
+/// `/pin` 不带文字时置顶当前选中的消息。
+pub(crate) fn room_command(msg: &str, tab: &RoomTab) -> Option<String> {
+    let (cmd, rest) = msg.split_once(' ').unwrap_or((msg, ""));
+    let rest = rest.trim();
+    let sealed = |text: &str| seal(&tab.key, text);
+    let args = match cmd {
+        "/room" => match rest.split_once(' ') {
+            Some((key @ ("topic" | "pin"), text)) => format!("{key} {}", sealed(text.trim())),
+            _ => rest.to_string(),
+        },
+        "/topic" if rest.is_empty() => "topic".to_string(),
+        "/topic" => format!("topic {}", sealed(rest)),
+        "/pin" if rest.is_empty() => {
+            let selected = tab.list_state.selected().and_then(|i| tab.messages.get(i));
+            match selected {
+                Some(msg) => {
+                    let (name, _, body) = parse_name_body(msg);
+                    format!("pin {}", sealed(&format!("{name}: {body}")))
+                }
+                None => "pin".to_string(),
+            }
+        }
+        "/pin" => format!("pin {}", sealed(rest)),
+        "/unpin" => format!("unpin {rest}"),
+        _ => return None,
+    };
+    Some(args)
+}
+
+/// 新开一个房间标签（已打开则直接切过去）
+fn join_room(ctx: &mut KeyCtx, room_id: &str, pwd: &str) {
+    if let Some(idx) = ctx.tabs.position(room_id) {
+        ctx.tabs.select(idx);
+        return;
+    }
+    let tab = RoomTab::new(room_id.to_string(), pwd.to_string());
+    let _ = ctx.out_tx.send(Outgoing::Join {
+        room:       tab.room_id.clone(),
+        credential: tab.credential(),
+        key:        tab.key,
+        hidden:     super::config::current().hide_new_rooms,
+    });
+    ctx.tabs.tabs.push(tab);
+    ctx.tabs.select(ctx.tabs.tabs.len() - 1);
+}
+
+/// 离开当前房间；最后一个房间关闭时交给调用方断开该服务器
+fn leave_current(ctx: &mut KeyCtx) -> ControlFlow {
+    let tab = ctx.tabs.remove(ctx.tabs.active);
+    if ctx.tabs.is_empty() {
+        return ControlFlow::Quit;
+    }
+    let _ = ctx.out_tx.send(Outgoing::Leave { room: tab.room_id });
+    ControlFlow::Continue
+}
+
 // 第 n 个字形单元在字符串中的字节偏移（从原 client.rs 搬过来）
 fn nth_grapheme_byte_idx(s: &str, n: usize) -> usize {
     s.grapheme_indices(true)
@@ -200,6 +307,12 @@ pub struct UndoMgr {
     max_depth:   usize,                // 可选：栈深上限
 }
 
+impl Default for UndoMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UndoMgr {
     pub fn new() -> Self {
         Self {
@@ -212,13 +325,13 @@ impl UndoMgr {
 
     /// 条件压栈：>500 ms 或操作类型变了
     pub fn maybe_push(&mut self,
-                  input: &String,
+                  input: &str,
                   cursor: usize,
                   kind: OpKind)
     {
         let elapsed = self.last_save.elapsed();
         if elapsed > Duration::from_millis(500) || kind != self.last_kind {
-            self.stack.push((input.clone(), cursor));
+            self.stack.push((input.to_string(), cursor));
             if self.stack.len() > self.max_depth {
                 self.stack.remove(0);            // 裁掉最早的
             }
@@ -8,4 +8,16 @@ pub mod initialization;
 pub mod handshake;
 pub mod clipboard;
 pub mod keyboard;
-pub mod ui;
\ No newline at end of file
+pub mod ui;
+pub mod config;
+pub mod addr;
+pub mod tabs;
+pub mod servers;
+pub mod frame;
+pub mod headless;
+pub mod cli;
+pub mod bot;
+pub mod webhook;
+pub mod irc;
+
+pub use headless::{Client, ClientEvent, Credentials, Events};
@@ -1,19 +1,167 @@
-use super::crypto::{server_open, server_seal, seal};   // open = 房间密钥的解密
-use super::utils::get_plaintext;
-use tokio::{io::{AsyncWriteExt, BufReader, Lines}, net::tcp::OwnedReadHalf,
+use super::crypto::{aead_open, room_hash, room_secrets, seal, RoomKey};   // seal = 房间密钥的加密
+use super::addr::ServerAddr;
+use super::handshake::{self, Login};
+use super::utils::{get_plaintext, seal_line};
+use std::collections::HashMap;
+use tokio::{io::AsyncWriteExt,
             sync::mpsc::{UnboundedReceiver, UnboundedSender},
-            time::{interval, Duration}};
+            time::{interval, sleep, Duration}};
 use anyhow::Result;
-use tokio::net::tcp::OwnedWriteHalf;
+
+/// UI → 网络
+#[derive(Debug)]
+pub enum Outgoing {
+    /// 发往某个房间的文本 / 图片路径 / `/IMGDATA…`
+    Text { room: String, text: String },
+    /// 原样发送的明文（不把图片路径当作图片读取），`/IMGDATA…` 即图片
+    Plain { room: String, text: String },
+    /// 在同一连接上再订阅一个房间（不存在则创建）
+    Join { room: String, credential: String, key: RoomKey, hidden: bool },
+    /// 退订房间
+    Leave { room: String },
+    /// 改昵称（本连接所有房间）
+    Nick { nick: String },
+    /// 房间设置（`/room` 查看，`/room capacity 20` 修改；主题需先用房间密钥加密）
+    Configure { room: String, args: String },
+    /// 管理命令（`kick alice`、`mute bob 10m` …），由服务器校验权限
+    Moderate { room: String, cmd: String },
+    /// 断开连接
+    Quit,
+}
+
+/// 网络 → UI（已去掉服务器层加密，房间层仍是密文）
+#[derive(Debug)]
+pub enum Incoming {
+    Room { room: String, line: String },
+    Joined { room: String },
+    JoinFailed { room: String, why: String },
+    /// 服务器确认的本连接昵称（改名成功或进房时自动加了后缀）
+    Nick { nick: String },
+    NickFailed { why: String },
+    /// 服务器欢迎信息（一行一条）
+    Motd { line: String },
+    /// 服务器管理员的公告
+    Announce { text: String },
+    /// 服务器即将关停，`secs` 秒后断开
+    Shutdown { secs: u64 },
+    /// 连接已断开（chat_loop 退出）
+    Closed,
+    /// 第 `attempt` 次重连将在 `delay` 秒后进行
+    Reconnecting { attempt: u32, delay: u64 },
+    /// 重连成功，已重新进入登录时的房间；`nickname` 为请求的昵称
+    Reconnected { room: String, nickname: String },
+}
+
+/// chat_loop 为什么结束
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ended {
+    /// 用户关闭（`Outgoing::Quit` 或发送端全部丢弃）
+    Quit,
+    /// 服务器断开或网络出错
+    Lost,
+}
+
+impl Incoming {
+    fn room_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Incoming::Room { room, .. } | Incoming::Joined { room } | Incoming::JoinFailed { room, .. } => Some(room),
+            _ => None,
+        }
+    }
+}
+
+/// 服务器下发的一行控制帧 → Incoming
+fn parse_incoming(plain: String) -> Option<Incoming> {
+    if let Some(rest) = plain.strip_prefix('#') {
+        let (room, line) = rest.split_once(' ')?;
+        return Some(Incoming::Room { room: room.to_string(), line: line.to_string() });
+    }
+    if let Some(room) = plain.strip_prefix("/join_ok ") {
+        return Some(Incoming::Joined { room: room.to_string() });
+    }
+    if let Some(text) = plain.strip_prefix("/announce ") {
+        return Some(Incoming::Announce { text: text.to_string() });
+    }
+    if let Some(secs) = plain.strip_prefix("/shutdown ") {
+        return Some(Incoming::Shutdown { secs: secs.trim().parse().unwrap_or(0) });
+    }
+    if let Some(line) = plain.strip_prefix("/motd ") {
+        return Some(Incoming::Motd { line: line.to_string() });
+    }
+    if let Some(why) = plain.strip_prefix("/nick_err ") {
+        return Some(Incoming::NickFailed { why: why.to_string() });
+    }
+    if let Some(nick) = plain.strip_prefix("/nick ") {
+        return Some(Incoming::Nick { nick: nick.to_string() });
+    }
+    if let Some(rest) = plain.strip_prefix("/join_err ") {
+        let (room, why) = rest.split_once(' ').unwrap_or((rest, ""));
+        return Some(Incoming::JoinFailed { room: room.to_string(), why: why.to_string() });
+    }
+    None
+}
+
+/// 重连退避上限（秒）
+const MAX_BACKOFF: u64 = 30;
+
+/// chat_loop + 断线重连：按 1、2、4…30 秒退避，直到重连成功或用户关闭该服务器
+///
+/// `server_addr` 为 `addr&pwd`；受邀请者没有服务器口令，断线后不重连。
+pub async fn run(
+    conn_id:     usize,
+    mut login:   Login,
+    server_addr: String,
+    net_tx:      UnboundedSender<(usize, Incoming)>,
+    mut out_rx:  UnboundedReceiver<Outgoing>,
+) {
+    let target = server_addr.split_once('&')
+        .and_then(|(addr, pwd)| Some((addr.parse::<ServerAddr>().ok()?, pwd.to_string())));
+    loop {
+        let (room, pwd, nickname, identity) = (login.room_id.clone(), login.pwd.clone(), login.nickname.clone(), login.identity);
+        match chat_loop(conn_id, login, net_tx.clone(), &mut out_rx).await {
+            Ok(Ended::Quit) => return,
+            Ok(Ended::Lost) => {}
+            Err(e) => eprintln!("chat_loop error: {e}"),
+        }
+        let Some((addr, server_pwd)) = &target else { return };
+        let mut attempt = 0;
+        login = loop {
+            attempt += 1;
+            let delay = (1u64 << (attempt - 1).min(5)).min(MAX_BACKOFF);
+            net_tx.send((conn_id, Incoming::Reconnecting { attempt, delay })).ok();
+            // 等待期间仍要响应关闭；离线时发的消息直接丢弃
+            let wait = sleep(Duration::from_secs(delay));
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    msg = out_rx.recv() => if matches!(msg, Some(Outgoing::Quit) | None) { return },
+                }
+            }
+            if let Ok(login) = handshake::connect_as(addr, server_pwd, &room, &pwd, &nickname, identity).await {
+                break login;
+            }
+        };
+        net_tx.send((conn_id, Incoming::Reconnected { room, nickname })).ok();
+    }
+}
+
+/// 一个服务器连接的收发循环；多个服务器各自一个任务，事件带 `conn_id` 汇入同一通道
 pub async fn chat_loop(
-    mut lines: Lines<BufReader<OwnedReadHalf>>,
-    mut writer: OwnedWriteHalf,
-    net_tx:      UnboundedSender<String>,
-    mut out_rx:  UnboundedReceiver<String>,
-) -> Result<()> {
+    conn_id:     usize,
+    login:       Login,
+    net_tx:      UnboundedSender<(usize, Incoming)>,
+    out_rx:      &mut UnboundedReceiver<Outgoing>,
+) -> Result<Ended> {
+    let Login { mut lines, mut writer, server_key, room_id, pwd, hashed, max_frame, .. } = login;
+    let first_key = room_secrets(&room_id, &pwd).0;
+    // 服务器上的房间号 → 房间名（哈希模式下服务器只认房间密钥派生的房间号）
+    let wire = |room: &str, key: &RoomKey| if hashed { room_hash(key) } else { room.to_string() };
+    let mut names: HashMap<String, String> = HashMap::from([(wire(&room_id, &first_key), room_id.clone())]);
+    let mut keys: HashMap<String, RoomKey> = HashMap::from([(room_id, first_key)]);
     let mut hb = interval(Duration::from_secs(30));
 
-    loop {
+    let result = loop {
         tokio::select! {
             /* ---------------- 1) 读 ---------------- */
             res = lines.next_line() => {
@@ -21,48 +169,81 @@ pub async fn chat_loop(
                     Ok(Some(line)) => {
                         if line == "/ping_ack" || line == "$$ping$$" { continue; }
 
-                        // ① 尝试用 SERVER_KEY 解密控制消息
-                        if let Some(plain) = server_open(&line) {
-                            net_tx.send(plain).ok();
-                            continue;
+                        // ① 尝试用本连接的服务器密钥解密控制消息
+                        if let Some(mut msg) = aead_open(&server_key, &line).and_then(parse_incoming) {
+                            if let Some(room) = msg.room_mut() {
+                                if let Some(name) = names.get(room.as_str()) {
+                                    *room = name.clone();
+                                }
+                            }
+                            net_tx.send((conn_id, msg)).ok();
                         }
                     }
-                    Ok(None) => { eprintln!("⚠️ Server closed the connection."); break; }
-                    Err(e)   => { eprintln!("⚠️ Failed to receive message: {e}"); break; }
+                    Ok(None) => break Ok(Ended::Lost),
+                    Err(e)   => break Err(e.into()),
                 }
             }
 
             /* ---------------- 2) 写 ---------------- */
             msg = out_rx.recv() => {
-                match msg {
-                    Some(text) if text == "//~``~//" => {
-                        writer.shutdown().await?;
-                        break;
-                    }
-                    Some(text) => {
-                        let plain = get_plaintext(&text).await?;
-                        let cipher_line = server_seal(seal(&plain));
-
-                        if writer.write_all(cipher_line.as_bytes()).await.is_err() {
-                            eprintln!("⚠️ Failed to send");
-                            break;
+                // 图片路径先读成 `/IMGDATA…`，之后与原样发送的明文一样处理
+                let msg = match msg {
+                    Some(Outgoing::Text { room, text }) => match get_plaintext(&text).await {
+                        Ok(text) => Some(Outgoing::Plain { room, text }),
+                        Err(e)   => { eprintln!("⚠️ {e}"); continue; }
+                    },
+                    msg => msg,
+                };
+                let frame = match msg {
+                    Some(Outgoing::Text { room, text }) | Some(Outgoing::Plain { room, text }) => {
+                        let Some(key) = keys.get(&room) else { continue };
+                        let frame = format!("#{} {}", wire(&room, key), seal(key, &text));
+                        // 超过服务器上限的帧会被拒绝并断开连接，不发出去
+                        // 服务器层加密：盐 + nonce + tag 后整体 base64
+                        let sealed_len = (frame.len() + 64).div_ceil(3) * 4;
+                        if max_frame.is_some_and(|max| sealed_len > max) {
+                            let line = "/mod_err FrameTooLarge".to_string();
+                            net_tx.send((conn_id, Incoming::Room { room, line })).ok();
+                            continue;
                         }
-                        let _ = writer.write_all(b"\n").await;
+                        frame
                     }
-                    None => {
-                        writer.shutdown().await?;
-                        break;
+                    Some(Outgoing::Join { room, credential, key, hidden }) => {
+                        let id = wire(&room, &key);
+                        keys.insert(room.clone(), key);
+                        names.insert(id.clone(), room);
+                        if hidden { format!("/join {id} {credential} hidden") } else { format!("/join {id} {credential}") }
                     }
+                    Some(Outgoing::Leave { room }) => {
+                        let Some(key) = keys.remove(&room) else { continue };
+                        format!("/leave {}", wire(&room, &key))
+                    }
+                    Some(Outgoing::Nick { nick }) => format!("/nick {nick}"),
+                    Some(Outgoing::Configure { room, args }) => {
+                        let Some(key) = keys.get(&room) else { continue };
+                        format!("/room {} {args}", wire(&room, key))
+                    }
+                    Some(Outgoing::Moderate { room, cmd }) => {
+                        let Some(key) = keys.get(&room) else { continue };
+                        format!("/mod {} {cmd}", wire(&room, key))
+                    }
+                    Some(Outgoing::Quit) | None => {
+                        break writer.shutdown().await.map(|_| Ended::Quit).map_err(Into::into);
+                    }
+                };
+                if let Err(e) = writer.write_all(&seal_line(&server_key, frame)).await {
+                    break Err(e.into());
                 }
             }
 
             /* ---------------- 3) 心跳 ---------------- */
             _ = hb.tick() => {
-                if writer.write_all(b"$$ping$$\n").await.is_err() {
-                    break;
+                if let Err(e) = writer.write_all(b"$$ping$$\n").await {
+                    break Err(e.into());
                 }
             }
         }
-    }
-    Ok(())
+    };
+    net_tx.send((conn_id, Incoming::Closed)).ok();
+    result
 }
@@ -10,6 +10,10 @@ pub fn notify() {
         },
     };
     use super::sounds;
+    let prefs = super::config::current().notify.clone();
+    if !prefs.enabled {
+        return;
+    }
         // 任务栏闪烁
         unsafe {
         let hwnd = GetConsoleWindow();
@@ -24,13 +28,19 @@ pub fn notify() {
             let _ = FlashWindowEx(&mut info);
         }
     }
-    std::thread::spawn(|| {
-        sounds::play_async();
-    });
+    if prefs.sound {
+        std::thread::spawn(|| {
+            sounds::play_async();
+        });
+    }
 }
 
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 pub fn notify() {
+    let prefs = &super::config::current().notify;
+    if !prefs.enabled || !prefs.sound {
+        return;
+    }
     // ① 终端响铃（ASCII 0x07）。对大多数 TTY / iTerm / GNOME Terminal 都生效
     print!("\x07");
     let _ = std::io::Write::flush(&mut std::io::stdout());
@@ -2,14 +2,18 @@
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use chrono::Local;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tui::widgets::ListState;
 use uuid::Uuid;
 use base64::{engine::general_purpose, Engine as _};
 use crate::client::utils::parse_text_img;
+use super::network::{Incoming, Outgoing};
 use super::notifier;
+use super::servers::Servers;
+use super::tabs::RoomTab;
+use super::crypto::{open, RoomKey};
 use std::path::Path;
 
 /// 区分文本消息和图片消息
@@ -23,20 +27,110 @@ pub enum ChatMessage {
     },
 }
 
-/// 将消息从网络通道里“抽干”到本地消息列表中
+/// 将消息从网络通道里“抽干”到对应服务器、对应房间的消息列表中
 pub fn drain_messages(
-    net_rx: &mut UnboundedReceiver<String>,
-    messages: &mut Vec<ChatMessage>,
-    list_state: &mut ListState,
-    my_name: &str,
+    net_rx: &mut UnboundedReceiver<(usize, Incoming)>,
+    servers: &mut Servers,
     img_dir: &Path,
-    members:    &mut Vec<String>, 
 ) {
-    while let Ok(line) = net_rx.try_recv() {
-        if line.starts_with("/member_list ") {
-                members.clear();
-                members.extend(
-                    line["/member_list ".len()..]
+    while let Ok((conn_id, msg)) = net_rx.try_recv() {
+        let visible_conn = servers.conns.get(servers.active).is_some_and(|c| c.id == conn_id);
+        let Some(conn) = servers.get_mut(conn_id) else { continue };
+        let tabs = &mut conn.tabs;
+        let (room, line) = match msg {
+            Incoming::Room { room, line } => (room, line),
+            Incoming::Joined { room } => {
+                if let Some(tab) = tabs.get_mut(&room) {
+                    tab.push_system("Joined.");
+                }
+                continue;
+            }
+            Incoming::JoinFailed { room, why } => {
+                if let Some(idx) = tabs.position(&room) {
+                    tabs.remove(idx);
+                }
+                if !tabs.is_empty() {
+                    tabs.current_mut().push_system(&format!("Failed to join [{room}]: {why}"));
+                }
+                continue;
+            }
+            Incoming::Nick { nick } => {
+                if nick != conn.nickname {
+                    for tab in &mut tabs.tabs {
+                        tab.push_system(&format!("You are now known as {nick}"));
+                    }
+                    conn.nickname = nick;
+                }
+                continue;
+            }
+            Incoming::Motd { line } => {
+                tabs.current_mut().push_system(&format!("📢 {line}"));
+                continue;
+            }
+            Incoming::NickFailed { why } => {
+                tabs.current_mut().push_system(&format!("Nickname change failed: {why}"));
+                continue;
+            }
+            Incoming::Announce { text } => {
+                notifier::notify();
+                for tab in &mut conn.tabs.tabs {
+                    tab.push_system(&format!("📣 [server] {text}"));
+                }
+                continue;
+            }
+            Incoming::Shutdown { secs } => {
+                conn.shutdown_at = Some(Instant::now() + Duration::from_secs(secs));
+                for tab in &mut conn.tabs.tabs {
+                    tab.push_system(&format!("🛑 Server is shutting down in {secs}s"));
+                }
+                continue;
+            }
+            Incoming::Closed => {
+                conn.connected = false;
+                for tab in &mut conn.tabs.tabs {
+                    tab.push_system("⚠️ Server closed the connection.");
+                }
+                continue;
+            }
+            Incoming::Reconnecting { attempt, delay } => {
+                conn.reconnect = Some((attempt, Instant::now() + Duration::from_secs(delay)));
+                continue;
+            }
+            Incoming::Reconnected { room, nickname } => {
+                conn.connected = true;
+                conn.shutdown_at = None;
+                conn.reconnect = None;
+                // 重连只进了登录时的房间：其余标签重新订阅，登录房间已关掉的退订
+                for tab in &conn.tabs.tabs {
+                    if tab.room_id != room {
+                        let _ = conn.out_tx.send(Outgoing::Join {
+                            room:       tab.room_id.clone(),
+                            credential: tab.credential(),
+                            key:        tab.key,
+                            hidden:     false,
+                        });
+                    }
+                }
+                if conn.tabs.get_mut(&room).is_none() {
+                    let _ = conn.out_tx.send(Outgoing::Leave { room });
+                }
+                if conn.nickname != nickname {
+                    let _ = conn.out_tx.send(Outgoing::Nick { nick: conn.nickname.clone() });
+                }
+                for tab in &mut conn.tabs.tabs {
+                    tab.push_system("✅ Reconnected.");
+                }
+                continue;
+            }
+        };
+        let my_name = conn.nickname.as_str();
+        let active = visible_conn && tabs.tabs.get(tabs.active).is_some_and(|t| t.room_id == room);
+        let Some(tab) = tabs.get_mut(&room) else { continue };
+
+        if let Some(list) = line.strip_prefix("/member_list ") {
+                tab.members.clear();
+                tab.members.extend(
+                    list
                         .split(',')
                         .map(str::trim)
                         .filter(|s| !s.is_empty())
@@ -45,27 +139,143 @@ pub fn drain_messages(
                 continue;
         }
 
-        // 拆分发送者、原始时间戳（这里不再用）和 body
-        let (sender, body) = parse_text_img(&line);
+        // 改名：自己的改名由 Incoming::Nick 提示
+        if let Some((old, new)) = line.strip_prefix("/renamed ").and_then(|r| r.split_once(' ')) {
+            if old != my_name && new != my_name {
+                tab.push_system(&format!("✏️ {old} is now known as {new}"));
+            }
+            continue;
+        }
+
+        // 连接太慢，服务器丢了消息；随后补发的部分以 `/history` 到达
+        if let Some(missed) = line.strip_prefix("/lagged ") {
+            tab.push_system(&format!("⚠️ Connection too slow: {missed} message(s) were dropped by the server"));
+            continue;
+        }
 
-        // ★ 只有别人发的才提醒
+        // 历史回放：`/history <unix 秒> [nick] ENC:…`，用原时间显示、不提醒
+        if let Some(rest) = line.strip_prefix("/history ") {
+            let Some((ts, line)) = rest.split_once(' ') else { continue };
+            let hms = ts.parse().ok()
+                .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+                .map(|t| t.with_timezone(&Local).format("%H:%M:%S").to_string())
+                .unwrap_or_default();
+            let (sender, body) = parse_text_img(line, &tab.key);
+            push_message(tab, sender, body, hms, img_dir);
+            continue;
+        }
+
+        // 主题 / 置顶：`/topic <by> [密文]`、`/pins <by> <密文>…`，by 为 `-` 时只同步不提示
+        if let Some(rest) = line.strip_prefix("/topic ") {
+            let (by, sealed) = rest.split_once(' ').unwrap_or((rest, ""));
+            tab.topic = (!sealed.is_empty()).then(|| open(&tab.key, sealed).unwrap_or_else(|| "<undecryptable>".into()));
+            if by != "-" {
+                let text = match &tab.topic {
+                    Some(topic) => format!("⚙️ {by} changed the topic: {topic}"),
+                    None => format!("⚙️ {by} cleared the topic"),
+                };
+                tab.push_system(&text);
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("/pins ") {
+            let mut it = rest.split_whitespace();
+            let by = it.next().unwrap_or("-");
+            tab.pins = it.map(|p| open(&tab.key, p).unwrap_or_else(|| "<undecryptable>".into())).collect();
+            if by != "-" {
+                tab.push_system(&format!("📌 {by} updated the pinned messages"));
+            }
+            continue;
+        }
+
+        // 管理事件与房间设置：以系统消息显示
+        if let Some(text) = moderation_text(&line, my_name).or_else(|| settings_text(&line, &tab.key)) {
+            if !active {
+                tab.unread += 1;
+            }
+            tab.push_system(&text);
+            continue;
+        }
+
+        // 拆分发送者与 body（body 已用房间密钥解密）
+        let (sender, body) = parse_text_img(&line, &tab.key);
+
+        // ★ 只有别人发的才提醒；非当前标签累计未读
         if sender != my_name {
             notifier::notify();
+            if !active {
+                tab.unread += 1;
+            }
         }
+        let hms = Local::now().format("%H:%M:%S").to_string();
+        push_message(tab, sender, body, hms, img_dir);
+    }
+}
+
+/// `/room_set <key> <by> <value>`、`/room_info k=v …` → 系统提示文本（主题用房间密钥解密）
+pub(super) fn settings_text(line: &str, key: &RoomKey) -> Option<String> {
+    let topic = |sealed: &str| open(key, sealed).unwrap_or_else(|| "<undecryptable>".into());
+    if let Some(rest) = line.strip_prefix("/room_set ") {
+        let mut it = rest.splitn(3, ' ');
+        let (setting, by, value) = (it.next()?, it.next()?, it.next().unwrap_or(""));
+        return Some(format!("⚙️ {by} set {setting} to {value}"));
+    }
+    let info = line.strip_prefix("/room_info ")?;
+    let fields: Vec<String> = info.split(' ')
+        .map(|kv| match kv.strip_prefix("topic=") {
+            Some(sealed) => format!("topic={}", topic(sealed)),
+            None => kv.to_string(),
+        })
+        .collect();
+    Some(format!("⚙️ Room settings: {}", fields.join(", ")))
+}
+
+/// `/mod …`、`/mod_err …`、`/slow_down …`、`/kicked …` → 系统提示文本
+pub(super) fn moderation_text(line: &str, my_name: &str) -> Option<String> {
+    if let Some(why) = line.strip_prefix("/mod_err ") {
+        return Some(format!("⛔ {why}"));
+    }
+    if let Some(strikes) = line.strip_prefix("/slow_down ") {
+        return Some(format!("🐢 You are sending too fast, messages are being dropped (warning {strikes})"));
+    }
+    if let Some(rest) = line.strip_prefix("/kicked ") {
+        let (by, kind) = rest.split_once(' ').unwrap_or((rest, "kick"));
+        let verb = if kind == "ban" { "banned" } else { "kicked" };
+        return Some(format!("🚫 You were {verb} by {by}. Press Esc to close this tab."));
+    }
+    let mut it = line.strip_prefix("/mod ")?.split_whitespace();
+    let (action, target, by) = (it.next()?, it.next()?, it.next()?);
+    let target = if target == my_name { "you" } else { target };
+    let text = match action {
+        "kick"   => format!("{by} kicked {target}"),
+        "ban"    => format!("{by} banned {target}"),
+        "unmute" => format!("{by} unmuted {target}"),
+        "op"     => format!("{by} made {target} a moderator"),
+        "mute"   => match it.next().and_then(|s| s.parse::<u64>().ok()) {
+            Some(secs) if secs > 0 => format!("{by} muted {target} for {secs}s"),
+            _ => format!("{by} muted {target}"),
+        },
+        _ => return None,
+    };
+    Some(format!("🛡️ {text}"))
+}
 
-        // 判断是否滚动到底部
-        let at_bottom = list_state
-            .selected()
-            .map(|i| i + 1 == messages.len())
-            .unwrap_or(true);
+fn push_message(tab: &mut RoomTab, sender: String, body: String, hms: String, img_dir: &Path) {
+    let messages = &mut tab.messages;
+    let list_state = &mut tab.list_state;
 
-        // 本地时间戳
-        let now = Local::now();
-        let hms = now.format("%H:%M:%S").to_string();
+    // 判断是否滚动到底部
+    let at_bottom = list_state
+        .selected()
+        .map(|i| i + 1 == messages.len())
+        .unwrap_or(true);
 
-        if body.starts_with("/IMGDATA") {
-            // 图片分支：去掉前缀，解 base64，写文件
-            let b64_data = &body["/IMGDATA".len()..];
+    if let Some(b64_data) = body.strip_prefix("/IMGDATA") {
+        // 图片分支：去掉前缀，解 base64，写文件
+        let limit = super::config::current().max_image_bytes;
+        if b64_data.len() / 4 * 3 > limit {
+            messages.push(ChatMessage::Text(format!("[{}] [{}] <Image too large>", sender, hms)));
+        } else {
             match general_purpose::STANDARD.decode(b64_data) {
                 Ok(bytes) => {
                     // 临时目录 ./rust_chat_images
@@ -89,25 +299,20 @@ pub fn drain_messages(
                     messages.push(ChatMessage::Text(fallback));
                 }
             }
-        } else {
-            // 文本分支：按旧逻辑加时间戳
-            let formatted = if let Some(pos) = line.find(']') {
-                // 保留原来中括号后的内容
-                let (left, right) = line.split_at(pos + 1);
-                format!("{} [{}]{}", left, hms, right)
-            } else {
-                format!("[{}] {}", hms, line)
-            };
-            messages.push(ChatMessage::Text(formatted));
         }
+    } else if sender != "???" {
+        // 文本分支：[name] [hh:mm:ss] 明文
+        messages.push(ChatMessage::Text(format!("[{}] [{}] {}", sender, hms, body)));
+    } else {
+        messages.push(ChatMessage::Text(format!("[{}] {}", hms, body)));
+    }
 
-        // 维持选中最后一条
-        if at_bottom {
-            list_state.select(Some(messages.len().saturating_sub(1)));
-        }
-        // 超过 500 条就删除前 100 条
-        if messages.len() > 500 {
-            messages.drain(..100);
-        }
+    // 维持选中最后一条
+    if at_bottom {
+        list_state.select(Some(messages.len().saturating_sub(1)));
+    }
+    // 超过 500 条就删除前 100 条
+    if messages.len() > 500 {
+        messages.drain(..100);
     }
 }
@@ -0,0 +1,120 @@
+// client/servers.rs
+//! 同时连接的多个服务器：每个服务器一个 chat_loop 任务和一组房间标签
+use std::time::Instant;
+
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use super::handshake::Login;
+use super::network::{self, Incoming, Outgoing};
+use super::tabs::{RoomTab, Tabs};
+
+pub struct ServerConn {
+    /// 区分网络事件来自哪个连接（不随增删变化）
+    pub id:          usize,
+    pub label:       String,
+    /// 本服务器上的昵称（可能被自动加后缀或 `/nick` 改过）
+    pub nickname:    String,
+    /// `addr&pwd`，生成邀请码用；受邀请者为空
+    pub server_addr: String,
+    pub out_tx:      UnboundedSender<Outgoing>,
+    pub tabs:        Tabs,
+    pub connected:   bool,
+    /// 服务器通知的关停时刻（倒计时横幅）
+    pub shutdown_at: Option<Instant>,
+    /// 断线重连中：(第几次, 下一次尝试的时刻)
+    pub reconnect:   Option<(u32, Instant)>,
+}
+
+impl ServerConn {
+    /// 标签栏上方的状态横幅：关停倒计时或重连进度
+    pub fn banner(&self) -> Option<String> {
+        let now = Instant::now();
+        if let Some((attempt, at)) = self.reconnect {
+            let secs = at.saturating_duration_since(now).as_secs();
+            return Some(if secs > 0 {
+                format!("⚠️ Disconnected, reconnecting in {secs}s (attempt {attempt})")
+            } else {
+                format!("⚠️ Disconnected, reconnecting… (attempt {attempt})")
+            });
+        }
+        let at = self.shutdown_at.filter(|_| self.connected)?;
+        let secs = at.saturating_duration_since(now).as_secs();
+        Some(format!("🛑 Server shutting down in {secs}s, will reconnect automatically"))
+    }
+
+    pub fn unread(&self) -> usize {
+        self.tabs.tabs.iter().map(|t| t.unread).sum()
+    }
+}
+
+pub struct Servers {
+    pub conns:  Vec<ServerConn>,
+    pub active: usize,
+    next_id:    usize,
+    net_tx:     UnboundedSender<(usize, Incoming)>,
+}
+
+impl Servers {
+    pub fn new(net_tx: UnboundedSender<(usize, Incoming)>) -> Self {
+        Self { conns: Vec::new(), active: 0, next_id: 0, net_tx }
+    }
+
+    /// 为握手成功的连接启动 chat_loop（断线自动重连），并切换到它
+    pub fn spawn(&mut self, label: String, server_addr: String, login: Login) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (out_tx, out_rx) = mpsc::unbounded_channel::<Outgoing>();
+        let tabs = Tabs::new(RoomTab::new(login.room_id.clone(), login.pwd.clone()));
+        let nickname = login.nickname.clone();
+        let net_tx = self.net_tx.clone();
+        tokio::spawn(network::run(id, login, server_addr.clone(), net_tx, out_rx));
+        self.conns.push(ServerConn {
+            id,
+            label,
+            nickname,
+            server_addr,
+            out_tx,
+            tabs,
+            connected: true,
+            shutdown_at: None,
+            reconnect: None,
+        });
+        self.active = self.conns.len() - 1;
+    }
+
+    pub fn current(&self) -> &ServerConn {
+        &self.conns[self.active]
+    }
+
+    pub fn current_mut(&mut self) -> &mut ServerConn {
+        &mut self.conns[self.active]
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut ServerConn> {
+        self.conns.iter_mut().find(|c| c.id == id)
+    }
+
+    pub fn next(&mut self) {
+        self.active = (self.active + 1) % self.conns.len();
+    }
+
+    pub fn prev(&mut self) {
+        self.active = (self.active + self.conns.len() - 1) % self.conns.len();
+    }
+
+    /// 关闭当前服务器（断开连接）
+    pub fn remove_current(&mut self) {
+        let conn = self.conns.remove(self.active);
+        let _ = conn.out_tx.send(Outgoing::Quit);
+        self.active = self.active.min(self.conns.len().saturating_sub(1));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conns.is_empty()
+    }
+
+    /// 所有服务器的未读总数
+    pub fn unread(&self) -> usize {
+        self.conns.iter().map(ServerConn::unread).sum()
+    }
+}
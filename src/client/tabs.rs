@@ -0,0 +1,112 @@
+// client/tabs.rs
+//! 一个连接上同时订阅的多个房间，每个房间一页标签
+use chrono::Local;
+use tui::widgets::ListState;
+
+use super::crypto::{room_secrets, RoomKey};
+use super::keyboard::UndoMgr;
+use super::receiver::ChatMessage;
+
+/// 单个房间的全部界面状态：消息、成员、未读数与输入草稿
+pub struct RoomTab {
+    pub room_id:    String,
+    pub pwd:        String,
+    pub key:        RoomKey,
+    pub messages:   Vec<ChatMessage>,
+    pub members:    Vec<String>,
+    pub list_state: ListState,
+    pub input:      String,
+    pub cursor:     usize,
+    pub undo_mgr:   UndoMgr,
+    pub unread:     usize,
+    /// 已用房间密钥解密的主题与置顶消息
+    pub topic:      Option<String>,
+    pub pins:       Vec<String>,
+}
+
+impl RoomTab {
+    pub fn new(room_id: String, pwd: String) -> Self {
+        let (key, _) = room_secrets(&room_id, &pwd);
+        Self {
+            room_id,
+            pwd,
+            key,
+            messages:   Vec::new(),
+            members:    Vec::new(),
+            list_state: ListState::default(),
+            input:      String::new(),
+            cursor:     0,
+            undo_mgr:   UndoMgr::new(),
+            unread:     0,
+            topic:      None,
+            pins:       Vec::new(),
+        }
+    }
+
+    pub fn credential(&self) -> String {
+        room_secrets(&self.room_id, &self.pwd).1
+    }
+
+    /// 本地系统提示（不经过服务器）
+    pub fn push_system(&mut self, text: &str) {
+        let hms = Local::now().format("%H:%M:%S");
+        self.messages.push(ChatMessage::Text(format!("[system] [{hms}] {text}")));
+        self.list_state.select(Some(self.messages.len() - 1));
+    }
+}
+
+pub struct Tabs {
+    pub tabs:   Vec<RoomTab>,
+    pub active: usize,
+}
+
+impl Tabs {
+    pub fn new(first: RoomTab) -> Self {
+        Self { tabs: vec![first], active: 0 }
+    }
+
+    pub fn current(&self) -> &RoomTab {
+        &self.tabs[self.active]
+    }
+
+    pub fn current_mut(&mut self) -> &mut RoomTab {
+        &mut self.tabs[self.active]
+    }
+
+    pub fn get_mut(&mut self, room_id: &str) -> Option<&mut RoomTab> {
+        self.tabs.iter_mut().find(|t| t.room_id == room_id)
+    }
+
+    pub fn position(&self, room_id: &str) -> Option<usize> {
+        self.tabs.iter().position(|t| t.room_id == room_id)
+    }
+
+    /// 切换标签并清零未读
+    pub fn select(&mut self, idx: usize) {
+        if idx < self.tabs.len() {
+            self.active = idx;
+            self.tabs[idx].unread = 0;
+        }
+    }
+
+    pub fn next(&mut self) {
+        self.select((self.active + 1) % self.tabs.len());
+    }
+
+    pub fn prev(&mut self) {
+        self.select((self.active + self.tabs.len() - 1) % self.tabs.len());
+    }
+
+    /// 关闭标签，返回被关闭的房间；关完后 `is_empty()`
+    pub fn remove(&mut self, idx: usize) -> RoomTab {
+        let tab = self.tabs.remove(idx);
+        if !self.tabs.is_empty() {
+            self.select(self.active.min(self.tabs.len() - 1));
+        }
+        tab
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tabs.is_empty()
+    }
+}
@@ -2,16 +2,58 @@
 use tui::{
     backend::Backend,
     Frame,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Tabs as TabBar},
 };
 use unicode_width::UnicodeWidthStr;
 use textwrap::wrap;
 use super::utils::parse_name_body;
-use super::receiver::ChatMessage;
+use super::servers::Servers;
+use super::tabs::{RoomTab, Tabs};
 use unicode_segmentation::UnicodeSegmentation;
+use super::config::{self, UiConfig};
+
+/// 由配置解析出的界面配色
+pub struct Theme {
+    pub border: Color,
+    pub own:    Color,
+    pub other:  Color,
+}
+
+impl Theme {
+    pub fn from_config(cfg: &UiConfig) -> Self {
+        Self {
+            border: parse_color(&cfg.border, Color::Rgb(0, 135, 0)),
+            own:    parse_color(&cfg.own, Color::Blue),
+            other:  parse_color(&cfg.other, Color::Red),
+        }
+    }
+}
+
+/// `#rrggbb` 或颜色名；无法识别时用 fallback
+fn parse_color(s: &str, fallback: Color) -> Color {
+    if let Some(hex) = s.strip_prefix('#') {
+        if let Ok([r, g, b]) = <[u8; 3]>::try_from(hex::decode(hex).unwrap_or_default()) {
+            return Color::Rgb(r, g, b);
+        }
+        return fallback;
+    }
+    match s.to_ascii_lowercase().as_str() {
+        "black"   => Color::Black,
+        "red"     => Color::Red,
+        "green"   => Color::Green,
+        "yellow"  => Color::Yellow,
+        "blue"    => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan"    => Color::Cyan,
+        "gray"    => Color::Gray,
+        "white"   => Color::White,
+        _         => fallback,
+    }
+}
+
 fn nth_grapheme_byte_idx(s: &str, n: usize) -> usize {
     s.grapheme_indices(true)
      .nth(n)
@@ -20,31 +62,67 @@ fn nth_grapheme_byte_idx(s: &str, n: usize) -> usize {
 }
 pub fn draw_chat<B: Backend>(
     f: &mut Frame<B>,
-    messages: &[ChatMessage],
-    list_state: &mut ListState,
-    member_list: &[String],
-    input: &str,
-    cursor: usize,
-    username: &str,
-    room_id: &str,
+    servers: &mut Servers,
 ) {
+    let theme = Theme::from_config(&config::current().ui);
     let size = f.size();
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints([
+            Constraint::Length(26),  // 服务器 / 房间树
+            Constraint::Min(1),
+        ])
+        .split(size);
+    draw_sidebar(f, servers, &theme, columns[0]);
+
+    let conn = servers.current_mut();
+    let banner = conn.banner();
+    let username = conn.nickname.as_str();
+    let tabs = &mut conn.tabs;
+    let outer = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),   // 房间标签
+            Constraint::Min(1),
+        ])
+        .split(columns[1]);
+    draw_tab_bar(f, tabs, banner, &theme, outer[0]);
+
+    // 有置顶时在聊天区上方留一个小面板（最多显示 3 条）
+    let pin_rows = tabs.current().pins.len().min(3) as u16;
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .margin(1)
         .constraints([
+            Constraint::Length(if pin_rows > 0 { pin_rows + 2 } else { 0 }),   // 置顶
             Constraint::Min(1),
             Constraint::Length(3),   // 成员栏
             Constraint::Length(5),   // 输入框
         ])
-        .split(size);
+        .split(outer[1]);
+    let (pin_area, chunks) = (chunks[0], &chunks[1..]);
 
-    let chat_inner_width = (chunks[0].width - 2) as usize;
+    let RoomTab { room_id, messages, members: member_list, list_state, input, cursor, topic, pins, .. } = tabs.current_mut();
+    if !pins.is_empty() {
+        let lines: Vec<Spans> = pins.iter().enumerate()
+            .map(|(i, pin)| Spans::from(format!("{}. {}", i + 1, pin)))
+            .collect();
+        f.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("📌 Pinned ({})", pins.len()))
+                    .style(Style::default().fg(theme.border))),
+            pin_area,
+        );
+    }
+    let cursor = *cursor;
+    let chat_inner_width = chunks[0].width.saturating_sub(2) as usize;
     const PREFIX_WIDTH: usize = 5;
 
     let items: Vec<ListItem> = messages.iter().map(|raw| {
         let (name, time, display_body) = parse_name_body(raw);
-        let color  = if name == username { Color::Blue } else { Color::Red };
+        let color  = if name == username { theme.own } else { theme.other };
 
         // ① 头行
         let mut spans = vec![Spans::from(
@@ -70,8 +148,11 @@ pub fn draw_chat<B: Backend>(
         List::new(items)
             .block(Block::default()
                 .borders(Borders::ALL)
-                .title(format!("<Room: {}>", room_id))
-                .style(Style::default().fg(Color::Rgb(0, 135, 0))))
+                .title(match topic {
+                    Some(topic) => format!("<Room: {}> {}", room_id, topic),
+                    None => format!("<Room: {}>", room_id),
+                })
+                .style(Style::default().fg(theme.border)))
             .highlight_symbol(">"),
         chunks[0],
         list_state,
@@ -86,28 +167,99 @@ pub fn draw_chat<B: Backend>(
     f.render_widget(
         Paragraph::new(members_text)
             .block(Block::default().borders(Borders::ALL).title("Members")
-            .style(Style::default().fg(Color::Rgb(0, 135, 0)))),
+            .style(Style::default().fg(theme.border))),
         chunks[1],
     );
 
     // —— Input —— //
     use tui::widgets::Wrap;
     f.render_widget(
-        Paragraph::new(input)
+        Paragraph::new(input.as_str())
             .wrap(Wrap { trim: false })
             .block(Block::default()
                 .borders(Borders::ALL)
                 .title(format!("{} >", username))
-                .style(Style::default().fg(Color::Rgb(0, 135, 0)))),
+                .style(Style::default().fg(theme.border))),
         chunks[2],
     );
 
     // —— 光标定位 —— //
-    let inner_width = (chunks[2].width - 2) as usize;
+    let inner_width = chunks[2].width.saturating_sub(2) as usize;
     let byte_idx = nth_grapheme_byte_idx(input, cursor);
     let prefix   = &input[..byte_idx];
     let wrapped  = wrap(prefix, inner_width);
     let cursor_y = wrapped.len() as u16 - 1;
     let cursor_x = wrapped.last().unwrap().as_ref().width() as u16;
     f.set_cursor(chunks[2].x + 1 + cursor_x, chunks[2].y + 1 + cursor_y);
-}
\ No newline at end of file
+}
+
+/// 左侧服务器树：服务器 → 房间，未读数在全部服务器间汇总
+fn draw_sidebar<B: Backend>(f: &mut Frame<B>, servers: &Servers, theme: &Theme, area: Rect) {
+    let mut items = Vec::new();
+    for (si, conn) in servers.conns.iter().enumerate() {
+        let mark = if conn.connected { "▼" } else { "✖" };
+        let unread = conn.unread();
+        let head = if unread > 0 {
+            format!("{mark} {} ({unread})", conn.label)
+        } else {
+            format!("{mark} {}", conn.label)
+        };
+        let style = if si == servers.active {
+            Style::default().fg(theme.own).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(theme.border)
+        };
+        items.push(ListItem::new(Span::styled(head, style)));
+        for (ti, tab) in conn.tabs.tabs.iter().enumerate() {
+            let mut line = format!("  #{}", tab.room_id);
+            if tab.unread > 0 {
+                line.push_str(&format!(" ({})", tab.unread));
+            }
+            let style = if si == servers.active && ti == conn.tabs.active {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else if tab.unread > 0 {
+                Style::default().fg(theme.other)
+            } else {
+                Style::default().fg(theme.border)
+            };
+            items.push(ListItem::new(Span::styled(line, style)));
+        }
+    }
+    let total = servers.unread();
+    let title = if total > 0 { format!("Servers ({total})") } else { "Servers (Alt+↑/↓)".to_string() };
+    f.render_widget(
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title)
+                .style(Style::default().fg(theme.border))),
+        area,
+    );
+}
+
+/// 顶部房间标签栏：未读数显示在房间名后；有关停倒计时或正在重连时标题换成醒目的横幅
+fn draw_tab_bar<B: Backend>(f: &mut Frame<B>, tabs: &Tabs, banner: Option<String>, theme: &Theme, area: Rect) {
+    let titles = tabs.tabs.iter().map(|t| {
+        let title = if t.unread > 0 {
+            format!("{} ({})", t.room_id, t.unread)
+        } else {
+            t.room_id.clone()
+        };
+        let style = if t.unread > 0 {
+            Style::default().fg(theme.other)
+        } else {
+            Style::default().fg(theme.border)
+        };
+        Spans::from(Span::styled(title, style))
+    }).collect();
+    let title = match banner {
+        Some(text) => Span::styled(text, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+        None => Span::raw("Rooms (Alt+←/→)"),
+    };
+    f.render_widget(
+        TabBar::new(titles)
+            .select(tabs.active)
+            .block(Block::default().borders(Borders::ALL).title(title)
+                .style(Style::default().fg(theme.border)))
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED)),
+        area,
+    );
+}
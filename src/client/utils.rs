@@ -1,4 +1,4 @@
-use super::crypto::{open,server_seal};
+use super::crypto::{aead_seal, open, server_seal, RoomKey};
 use super::receiver::ChatMessage;
 pub const HELP_TEXT: &str = r#"快捷键与命令说明：
 
@@ -10,7 +10,15 @@ pub const HELP_TEXT: &str = r#"快捷键与命令说明：
 • ←/→          → 移动光标（Ctrl+← 跳3字符，Ctrl+→ 跳至末尾）  
 • ↑/↓          → 列表选上下（Ctrl+↑ 跳 5 条，Ctrl+↓ 跳到底部）  
 • Tab          → 打开选中行的图片  
-• Esc          → 退出房间  "#;
+• Alt+←/→      → 切换房间标签（Alt+1..9 直达）
+• /join 房间 [密码] → 在新标签中加入房间
+• /connect 地址 [服务器密码] [房间] [密码] 或 /connect 邀请码 → 同时连接另一台服务器（省略密码时用地址簿中保存的）
+• Alt+↑/↓      → 切换服务器
+• /nick 新昵称  → 改名（在该服务器的所有房间生效）
+• /room [设置 值] → 查看/修改房间设置（persist on|off、topic、capacity、visibility、retention、rate）
+• /topic [主题]、/pin [文字]、/unpin [序号] → 房间主题与置顶（管理员；/pin 不带文字置顶选中消息，主题与置顶端到端加密）
+• /kick /ban 昵称（ban 按身份和 IP 封禁）、/mute 昵称 [10m]、/unmute 昵称、/op 昵称 → 房间管理（房主/管理员）
+• Esc / /leave → 离开当前房间（最后一个房间则断开）  "#;
 pub const HELP_TEXT_EN: &str = r#"Keyboard Shortcuts and Command Descriptions:
 
 • Ctrl+X       → Paste clipboard text/image
@@ -21,13 +29,27 @@ pub const HELP_TEXT_EN: &str = r#"Keyboard Shortcuts and Command Descriptions:
 • ←/→          → Move cursor (Ctrl+← jump 3 characters, Ctrl+→ jump to end)
 • ↑/↓          → Navigate list up/down (Ctrl+↑ jump 5 items, Ctrl+↓ jump to bottom)
 • Tab          → Open the image in the selected row
-• Esc          → Exit room"#;
+• Alt+←/→      → Switch room tabs (Alt+1..9 to jump)
+• /join room [pwd] → Join another room in a new tab
+• /connect host [server_pwd] [room] [pwd] or /connect invite → Connect to another server (saved password if omitted)
+• Alt+↑/↓      → Switch server
+• /nick new_name → Change nickname (all rooms on this server)
+• /room [setting value] → Show/change room settings (persist on|off, topic, capacity, visibility, retention, rate)
+• /topic [text], /pin [text], /unpin [n] → Room topic and pins (moderators; /pin alone pins the selected message; end-to-end encrypted)
+• /kick /ban nick (ban blocks the identity and IP), /mute nick [10m], /unmute nick, /op nick → Moderation (owner/moderators)
+• Esc / /leave → Leave current room (disconnects after the last one)"#;
 pub fn handshake_writeall_macro(line:String) -> Vec<u8> {
     let mut buf = server_seal(line.to_string()).into_bytes();
     buf.push(b'\n');
     buf
 }
-pub fn parse_text_img(line: &str) -> (String, String) {
+/// 同上，但使用指定连接的服务器密钥
+pub fn seal_line(key: &[u8; 32], line: String) -> Vec<u8> {
+    let mut buf = aead_seal(key, &line).into_bytes();
+    buf.push(b'\n');
+    buf
+}
+pub fn parse_text_img(line: &str, key: &RoomKey) -> (String, String) {
     // 1. 先找出第一对 [name]
     let (name, after_name) = if let Some(start) = line.find('[') {
         if let Some(end_rel) = line[start + 1..].find(']') {
@@ -44,7 +66,7 @@ pub fn parse_text_img(line: &str) -> (String, String) {
 
     // 2. 剥掉 body 前的空格，尝试解密
     let body_slice = after_name.trim_start();
-    let body_plain = open(body_slice).unwrap_or_else(|| body_slice.to_owned());
+    let body_plain = open(key, body_slice).unwrap_or_else(|| body_slice.to_owned());
 
     (name, body_plain)
 }
@@ -80,11 +102,8 @@ pub fn parse_name_body(msg: &ChatMessage) -> (String, String, String) {
                 ("??:??:??".into(), after_name)
             };
 
-            // 3. 解密 body
-            let body_slice = after_time.trim_start();
-            let body_plain = open(body_slice).unwrap_or_else(|| body_slice.to_owned());
-
-            (name, time, body_plain)
+            // 3. body 在收到时已解密
+            (name, time, after_time.trim_start().to_owned())
         }
 
         ChatMessage::Image { path,sender, ts } => {
@@ -130,6 +149,10 @@ pub async fn get_plaintext(msg: &str) -> Result<String> {
         .unwrap_or(false);
 
     if is_img {
+        let limit = super::config::current().max_image_bytes;
+        if fs::metadata(path).await?.len() as usize > limit {
+            return Err(anyhow::anyhow!("image larger than {limit} bytes"));
+        }
         // 读整个文件
         let data = fs::read(path).await?;
         // Base64 编码
@@ -170,9 +193,10 @@ use serde::{Serialize, Deserialize};
 use chrono::Utc;
 use rand::RngCore;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD};
+use super::addr::ServerAddr;
 #[derive(Serialize, Deserialize)]
 struct Invite {
-    server:   String,
+    server:   ServerAddr,
     enc_pwd:  [u8; 32],
     room_id:  String,
     room_key: String,
@@ -187,7 +211,7 @@ fn derive_invite_key() -> [u8; 32] {
     }
     key
 }
-pub fn create_invitation(server_addr:String,server_pwd:String,room_id:String,pwd:String) 
+pub fn create_invitation(server_addr:ServerAddr,server_pwd:String,room_id:String,pwd:String) 
         -> Result<String, Box<dyn std::error::Error>>{
     let key = derive_invite_key();
     // 随机 12 字节 nonce
@@ -216,7 +240,7 @@ pub fn create_invitation(server_addr:String,server_pwd:String,room_id:String,pwd
     Ok(URL_SAFE_NO_PAD.encode(out))
 }
 
-pub fn parse_invitation(inv: &str) -> Option<(String, [u8; 32], String, String)> {
+pub fn parse_invitation(inv: &str) -> Option<(ServerAddr, [u8; 32], String, String)> {
     let raw = inv.strip_prefix("/INVITE:")?;
 
     // ---------- A. 尝试 URL-safe Base64 ----------
@@ -247,7 +271,7 @@ pub fn parse_invitation(inv: &str) -> Option<(String, [u8; 32], String, String)>
 
 pub fn inviation_clear(inv: &str) -> String{
     if inv.starts_with("/INVITE:"){
-        format!("")
+        String::new()
     } else {
         inv.to_string()
     }
@@ -0,0 +1,308 @@
+// client/webhook.rs
+//! 本机 webhook 入口：把构建、监控系统的 HTTP 请求转成房间里的加密消息
+//!
+//! ```text
+//! curl -H "Authorization: Bearer $TOKEN" -d '{"room":"ops","text":"deploy done"}' http://127.0.0.1:8787/message
+//! ```
+//! 内部是一个普通的无界面客户端，消息用房间密钥加密后再发给服务器。
+//! 每个令牌只能发往配置里列出的房间；和 metrics 一样不依赖 HTTP 框架，一个请求一个连接。
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::addr::ServerAddr;
+use super::headless::{Client, ClientEvent, Credentials, Events};
+
+pub const DEFAULT_WEBHOOK_BIND: &str = "127.0.0.1:8787";
+pub const DEFAULT_WEBHOOK_PORT: u16 = 8787;
+
+/// 请求头上限
+const MAX_HEAD: usize = 8 * 1024;
+/// 请求体上限
+const MAX_BODY: usize = 64 * 1024;
+
+/// webhook.toml
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// 聊天服务器地址
+    pub server:        ServerAddr,
+    /// HTTP 监听地址，默认只监听本机
+    #[serde(default = "default_bind")]
+    pub bind:          String,
+    #[serde(default = "default_nickname")]
+    pub nickname:      String,
+    /// 服务器口令文件；不设则读 `CHAT_SERVER_PASSWORD`
+    pub password_file: Option<PathBuf>,
+    /// 要进入的房间及其密码；令牌里出现但这里没列的房间使用空密码
+    #[serde(default, rename = "room")]
+    pub rooms:         Vec<RoomEntry>,
+    #[serde(rename = "token")]
+    pub tokens:        Vec<TokenEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoomEntry {
+    pub name:     String,
+    #[serde(default)]
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenEntry {
+    /// 日志里显示的名字
+    pub name:  String,
+    pub token: String,
+    /// 允许发往的房间
+    pub rooms: Vec<String>,
+}
+
+fn default_bind() -> String {
+    DEFAULT_WEBHOOK_BIND.to_string()
+}
+
+fn default_nickname() -> String {
+    "webhook".to_string()
+}
+
+impl WebhookConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        let cfg: WebhookConfig = toml::from_str(&text).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        if cfg.tokens.iter().any(|t| t.token.len() < 16) {
+            bail!("{}: tokens must be at least 16 characters", path.display());
+        }
+        Ok(cfg)
+    }
+
+    /// 所有令牌涉及的房间及其密码
+    fn room_passwords(&self) -> Vec<(String, String)> {
+        let mut names: Vec<&String> = self.tokens.iter().flat_map(|t| &t.rooms).collect();
+        names.sort();
+        names.dedup();
+        names.into_iter()
+            .map(|name| {
+                let pwd = self.rooms.iter().find(|r| r.name == *name).map(|r| r.password.clone());
+                (name.clone(), pwd.unwrap_or_default())
+            })
+            .collect()
+    }
+}
+
+/// 只保存令牌的 SHA-256：比较摘要，逐字节比较的耗时不会泄露令牌内容
+struct Token {
+    name:   String,
+    digest: [u8; 32],
+    rooms:  HashSet<String>,
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// 请求处理与事件任务共享的状态
+struct State {
+    client: Client,
+    tokens: Vec<Token>,
+    /// 与服务器的连接是否正常；断线期间发的消息会被客户端丢弃，直接回 503
+    online: AtomicBool,
+    /// 当前在里面的房间（被踢出或重进失败的会移除）
+    rooms:  Mutex<HashSet<String>>,
+}
+
+pub struct Webhook {
+    listener: TcpListener,
+    state:    Arc<State>,
+    events:   Events,
+}
+
+impl Webhook {
+    /// 连接服务器、进入所有房间并绑定 HTTP 端口
+    pub async fn start(cfg: WebhookConfig, server_password: String) -> Result<Webhook> {
+        let rooms = cfg.room_passwords();
+        let (client, events) = Client::connect(cfg.server.clone(), Credentials::new(server_password, cfg.nickname.clone())).await?;
+        for (room, pwd) in &rooms {
+            client.join(room, pwd).await.map_err(|e| anyhow!("{room}: {e}"))?;
+        }
+        let addr = ServerAddr::parse_with_port(&cfg.bind, DEFAULT_WEBHOOK_PORT)?;
+        let listener = addr.bind().await.map_err(|e| anyhow!("webhook {addr}: {e}"))?;
+        let tokens = cfg.tokens.iter()
+            .map(|t| Token { name: t.name.clone(), digest: digest(&t.token), rooms: t.rooms.iter().cloned().collect() })
+            .collect();
+        let state = Arc::new(State {
+            client,
+            tokens,
+            online: AtomicBool::new(true),
+            rooms:  Mutex::new(rooms.into_iter().map(|(room, _)| room).collect()),
+        });
+        Ok(Webhook { listener, state, events })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// 处理请求直到与服务器的连接彻底结束
+    pub async fn run(self) -> Result<()> {
+        let Webhook { listener, state, mut events } = self;
+        let accept = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Ok((stream, peer)) = listener.accept().await else { continue };
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        let _ = tokio::time::timeout(Duration::from_secs(10), respond(stream, peer, &state)).await;
+                    });
+                }
+            })
+        };
+        while let Some(event) = events.next().await {
+            track(&state, event);
+        }
+        accept.abort();
+        Err(anyhow!("connection to the chat server closed"))
+    }
+}
+
+/// 根据客户端事件更新在线状态与房间
+fn track(state: &State, event: ClientEvent) {
+    match event {
+        ClientEvent::Disconnected => state.online.store(false, Ordering::Relaxed),
+        ClientEvent::Reconnected => state.online.store(true, Ordering::Relaxed),
+        ClientEvent::Joined { room } => {
+            state.rooms.lock().unwrap().insert(room);
+        }
+        ClientEvent::JoinFailed { room, why } => {
+            eprintln!("webhook: cannot join {room}: {why}");
+            state.rooms.lock().unwrap().remove(&room);
+        }
+        ClientEvent::Kicked { room, by, .. } => {
+            eprintln!("webhook: kicked from {room} by {by}");
+            state.rooms.lock().unwrap().remove(&room);
+        }
+        ClientEvent::Error { room: Some(room), why } => eprintln!("webhook: {room}: {why}"),
+        _ => {}
+    }
+}
+
+#[derive(Deserialize)]
+struct Post {
+    room: String,
+    text: String,
+}
+
+async fn respond(mut stream: TcpStream, peer: SocketAddr, state: &State) -> std::io::Result<()> {
+    let (status, body) = match read_request(&mut stream).await? {
+        Ok((head, body)) => handle(&head, &body, peer, state).await,
+        Err(status) => (status, "bad request"),
+    };
+    let body = json!({ "ok": status.starts_with("200"), "message": body }).to_string();
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// 读请求头与请求体；格式错误返回对应的状态行
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Result<(String, Vec<u8>), &'static str>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD {
+            return Ok(Err("431 Request Header Fields Too Large"));
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(Err("400 Bad Request"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+    let length = header(&head, "content-length").map(|v| v.parse::<usize>());
+    let length = match length {
+        Some(Ok(n)) if n <= MAX_BODY => n,
+        Some(Ok(_)) => return Ok(Err("413 Payload Too Large")),
+        Some(Err(_)) => return Ok(Err("400 Bad Request")),
+        None => 0,
+    };
+    let mut body = buf.split_off(end + 4);
+    // curl 发送较大的请求体前会先等 100 Continue
+    if body.len() < length && header(&head, "expect").is_some_and(|v| v.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+    while body.len() < length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(Err("400 Bad Request"));
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(length);
+    Ok(Ok((head, body)))
+}
+
+/// 请求头取值（名字不区分大小写）
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+async fn handle(head: &str, body: &[u8], peer: SocketAddr, state: &State) -> (&'static str, &'static str) {
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (request_line.next(), request_line.next());
+    match (method, path) {
+        (Some("POST"), Some("/message")) => {}
+        (_, Some("/message")) => return ("405 Method Not Allowed", "use POST"),
+        _ => return ("404 Not Found", "not found"),
+    }
+    let presented = header(head, "authorization").and_then(|v| v.strip_prefix("Bearer ")).map(|t| digest(t.trim()));
+    let Some(token) = presented.and_then(|d| state.tokens.iter().find(|t| t.digest == d)) else {
+        return ("401 Unauthorized", "missing or invalid token");
+    };
+    let Ok(post) = serde_json::from_slice::<Post>(body) else {
+        return ("400 Bad Request", "expected JSON {\"room\": ..., \"text\": ...}");
+    };
+    if post.text.trim().is_empty() {
+        return ("400 Bad Request", "empty text");
+    }
+    if !token.rooms.contains(&post.room) {
+        eprintln!("webhook: {peer} token={} room={} denied", token.name, post.room);
+        return ("403 Forbidden", "token not allowed to post in this room");
+    }
+    if !state.online.load(Ordering::Relaxed) || !state.rooms.lock().unwrap().contains(&post.room) {
+        return ("503 Service Unavailable", "not connected to this room, try again later");
+    }
+    if state.client.send_text(&post.room, &post.text).await.is_err() {
+        return ("503 Service Unavailable", "connection to the chat server closed");
+    }
+    eprintln!("webhook: {peer} token={} room={} bytes={}", token.name, post.room, post.text.len());
+    ("200 OK", "sent")
+}
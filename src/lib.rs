@@ -1,6 +1,7 @@
 // lib.rs
 
 pub mod client;
+pub mod server;
 
 #[cfg(test)]
 mod tests {
@@ -17,4 +18,1017 @@ mod tests {
         // 如果你用了 `use crate::client::notifier::notify;`：
         // notify();
     }
+
+    #[test]
+    fn test_vault_roundtrip() {
+        use crate::client::crypto::{vault_open, vault_seal};
+        let sealed = vault_seal("master", "server-pwd");
+        assert_eq!(vault_open("master", &sealed).as_deref(), Some("server-pwd"));
+        assert!(vault_open("wrong", &sealed).is_none());
+    }
+
+    #[test]
+    fn test_saved_server_password() {
+        use crate::client::{addr::ServerAddr, config::{ClientConfig, ServerEntry}, crypto::vault_seal, initialization::saved_server_password};
+        let cfg = ClientConfig {
+            servers: vec![ServerEntry { name: "Home".into(), addr: "10.0.0.2:6655".into(), password: Some(vault_seal("master", "server-pwd")) }],
+            ..ClientConfig::default()
+        };
+        let addr: ServerAddr = "10.0.0.2".parse().unwrap();
+        // 按输入的 host 找不到时按规范化后的地址找
+        assert_eq!(saved_server_password(&cfg, "10.0.0.2", &addr, Some("master")).unwrap(), "server-pwd");
+        assert!(saved_server_password(&cfg, "10.0.0.2", &addr, Some("wrong")).is_err());
+        assert!(saved_server_password(&cfg, "10.0.0.2", &addr, None).is_err());
+        let other: ServerAddr = "10.0.0.3".parse().unwrap();
+        assert!(saved_server_password(&cfg, "10.0.0.3", &other, Some("master")).is_err());
+    }
+
+    #[test]
+    fn test_client_config_toml() {
+        use crate::client::config::ClientConfig;
+        let cfg: ClientConfig = toml::from_str(r#"
+            nickname = "alice"
+            [[server]]
+            name = "Home"
+            addr = "10.0.0.2:6655"
+        "#).unwrap();
+        assert_eq!(cfg.nickname.as_deref(), Some("alice"));
+        assert_eq!(cfg.find_server("10.0.0.2:6655").unwrap().name, "Home");
+        assert!(cfg.notify.enabled);
+        assert!(toml::to_string_pretty(&cfg).is_ok());
+    }
+
+    #[test]
+    fn test_server_addr_parse() {
+        use crate::client::addr::ServerAddr;
+        let a: ServerAddr = "chat.example.lan:7000".parse().unwrap();
+        assert_eq!((a.host.as_str(), a.port), ("chat.example.lan", 7000));
+        assert_eq!("[::1]:6655".parse::<ServerAddr>().unwrap().to_string(), "[::1]:6655");
+        assert_eq!("::1".parse::<ServerAddr>().unwrap().port, 6655);
+        assert_eq!("10.0.0.2".parse::<ServerAddr>().unwrap().to_string(), "10.0.0.2:6655");
+        assert_eq!(ServerAddr::parse_with_port("[::]", 7000).unwrap().to_string(), "[::]:7000");
+        assert!("300.1.1.1:80".parse::<ServerAddr>().is_err());
+        assert!("bad_host:1".parse::<ServerAddr>().is_err());
+        assert!("host:99999".parse::<ServerAddr>().is_err());
+    }
+
+    #[test]
+    fn test_server_identity() {
+        use crate::client::crypto::server_identity;
+        let secret = [7u8; 32];
+        let a = server_identity(&secret, &[1u8; 32]);
+        assert_eq!(a, server_identity(&secret, &[1u8; 32]));
+        assert_ne!(a, server_identity(&secret, &[2u8; 32]));
+        assert_eq!(a.len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_frame_reader_limit() {
+        use crate::client::frame::{is_too_large, FrameReader};
+        let data = format!("short\r\n{}\nafter\n", "x".repeat(100));
+        let mut r = FrameReader::new(data.as_bytes(), 16);
+        assert_eq!(r.next_line().await.unwrap().as_deref(), Some("short"));
+        assert!(is_too_large(&r.next_line().await.unwrap_err()));
+        let mut r = FrameReader::new("tail".as_bytes(), 16);
+        assert_eq!(r.next_line().await.unwrap().as_deref(), Some("tail"));
+        assert_eq!(r.next_line().await.unwrap(), None);
+    }
+
+    /// 等到事件流里第一个满足条件的事件
+    async fn until<S: futures_util::Stream<Item = crate::client::ClientEvent> + Unpin>(
+        events: &mut S,
+        want: impl Fn(&crate::client::ClientEvent) -> bool,
+    ) -> crate::client::ClientEvent {
+        use futures_util::StreamExt;
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let event = events.next().await.expect("stream ended");
+                if want(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out")
+    }
+
+    /// 本机测试服务器，端口由系统分配
+    async fn local_server() -> crate::server::Server {
+        crate::server::Server::builder().bind("127.0.0.1:0").build().await.unwrap()
+    }
+
+    /// 发一条管理命令，返回完整回复
+    #[cfg(unix)]
+    async fn admin(path: &std::path::Path, cmd: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut conn = tokio::net::UnixStream::connect(path).await.unwrap();
+        conn.write_all(format!("{cmd}\n").as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        conn.read_to_string(&mut reply).await.unwrap();
+        reply
+    }
+
+    #[tokio::test]
+    async fn test_server_ephemeral_port() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        let server = local_server().await;
+        let addr = server.local_addrs()[0];
+        assert_ne!(addr.port(), 0);
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(server.run());
+        let mut conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+        conn.write_all(b"AUTH nope\n").await.unwrap();
+        let mut reply = String::new();
+        BufReader::new(&mut conn).read_line(&mut reply).await.unwrap();
+        assert_eq!(reply.trim(), "ERR BadAuth");
+        shutdown.shutdown();
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_headless_client() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (alice, mut alice_events) = Client::connect(addr.clone(), Credentials::new(DEFAULT_PASSWORD, "alice")).await.unwrap();
+        assert!(alice.send_text("lobby", "too early").await.is_err());
+        alice.join("lobby", "pw").await.unwrap();
+        until(&mut alice_events, |e| matches!(e, ClientEvent::Joined { room } if room == "lobby")).await;
+
+        let (bob, _bob_events) = Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "bob")).await.unwrap();
+        bob.join("lobby", "pw").await.unwrap();
+        until(&mut alice_events, |e| matches!(e, ClientEvent::UserJoined { nick, .. } if nick == "bob")).await;
+        until(&mut alice_events, |e| matches!(e, ClientEvent::MemberList { members, .. } if members.len() == 2)).await;
+
+        bob.send_text("lobby", "hi.png").await.unwrap();
+        let msg = until(&mut alice_events, |e| matches!(e, ClientEvent::Message { .. })).await;
+        assert!(matches!(msg, ClientEvent::Message { sender, text, history: false, .. } if sender == "bob" && text == "hi.png"));
+        bob.send_image("lobby", &[1, 2, 3]).await.unwrap();
+        let img = until(&mut alice_events, |e| matches!(e, ClientEvent::Image { .. })).await;
+        assert!(matches!(img, ClientEvent::Image { data, .. } if data == [1, 2, 3]));
+
+        bob.leave("lobby").await.unwrap();
+        until(&mut alice_events, |e| matches!(e, ClientEvent::UserLeft { nick, .. } if nick == "bob")).await;
+        alice.quit().await.unwrap();
+        until(&mut alice_events, |e| *e == ClientEvent::Disconnected).await;
+        shutdown.shutdown();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_duplicate_nicks_join_concurrently() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+
+        let mut cfg = ServerConfig::default();
+        cfg.limits.auth_burst = 16;
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (first, mut first_events) = Client::connect(addr.clone(), Credentials::new(DEFAULT_PASSWORD, "dup")).await.unwrap();
+        first.join("crowded", "pw").await.unwrap();
+        let mut clients = Vec::new();
+        for _ in 0..8 {
+            clients.push(Client::connect(addr.clone(), Credentials::new(DEFAULT_PASSWORD, "dup")).await.unwrap());
+        }
+        // 同名的几个连接同时进房：后缀在同一把锁里挑选，谁也不会因为撞名被拒
+        let joins = clients.iter().map(|(client, _)| client.join("crowded", "pw"));
+        for result in futures_util::future::join_all(joins).await {
+            result.unwrap();
+        }
+        let list = until(&mut first_events, |e| matches!(e, ClientEvent::MemberList { members, .. } if members.len() == 9)).await;
+        let ClientEvent::MemberList { mut members, .. } = list else { unreachable!() };
+        let mut expected: Vec<_> = std::iter::once("dup".to_string()).chain((2..=9).map(|n| format!("dup_{n}"))).collect();
+        members.sort();
+        expected.sort();
+        assert_eq!(members, expected);
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_nick_rename_broadcast() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (alice, mut alice_events) = Client::connect(addr.clone(), Credentials::new(DEFAULT_PASSWORD, "alice")).await.unwrap();
+        alice.join("names", "pw").await.unwrap();
+        let (bob, mut bob_events) = Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "bob")).await.unwrap();
+        bob.join("names", "pw").await.unwrap();
+        until(&mut alice_events, |e| matches!(e, ClientEvent::MemberList { members, .. } if members.len() == 2)).await;
+
+        bob.set_nick("robert").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Nick { nick } if nick == "robert")).await;
+        assert_eq!(bob.nickname(), "robert");
+        until(&mut alice_events, |e| matches!(e, ClientEvent::Notice { text, .. } if text == "bob is now known as robert")).await;
+        until(&mut alice_events, |e| matches!(e, ClientEvent::MemberList { members, .. } if members.contains(&"robert".to_string()))).await;
+
+        alice.set_nick("robert").await.unwrap();
+        let err = until(&mut alice_events, |e| matches!(e, ClientEvent::Error { room: None, .. })).await;
+        assert!(matches!(err, ClientEvent::Error { why, .. } if why == "NickTaken"));
+        assert_eq!(alice.nickname(), "alice");
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_ban_survives_new_identity() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        let creds = |nick: &str, id: u8| Credentials::new(DEFAULT_PASSWORD, nick).with_identity([id; 32]);
+
+        let (owner, mut owner_events) = Client::connect(addr.clone(), creds("owner", 1)).await.unwrap();
+        owner.join("guarded", "pw").await.unwrap();
+        let (bob, mut bob_events) = Client::connect(addr.clone(), creds("bob", 2)).await.unwrap();
+        bob.join("guarded", "pw").await.unwrap();
+        until(&mut owner_events, |e| matches!(e, ClientEvent::UserJoined { nick, .. } if nick == "bob")).await;
+
+        owner.moderate("guarded", "ban bob").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Kicked { ban: true, .. })).await;
+        // 客户端已忘掉这个房间，重连时不会再 rejoin
+        let err = bob.set_topic("guarded", "back").await.unwrap_err();
+        assert!(err.to_string().contains("not in room"), "{err}");
+
+        // 换一个身份（相当于删掉 identity 文件）仍被同一 IP 的封禁挡住
+        let (again, _) = Client::connect(addr.clone(), creds("bob", 3)).await.unwrap();
+        let err = again.join("guarded", "pw").await.unwrap_err();
+        assert!(err.to_string().contains("Banned"), "{err}");
+        // 封禁只针对这个房间，房主也不会被同一 IP 上的封禁挡住
+        let (elsewhere, _) = Client::connect(addr.clone(), creds("bob", 3)).await.unwrap();
+        elsewhere.join("other", "pw").await.unwrap();
+        let (owner2, _) = Client::connect(addr, creds("owner", 1)).await.unwrap();
+        owner2.join("guarded", "pw").await.unwrap();
+        shutdown.shutdown();
+    }
+
+    /// 不经过客户端库的原始连接
+    type Raw = tokio::io::BufReader<tokio::net::TcpStream>;
+
+    /// 用服务器密钥加密发送一行
+    async fn raw_send(conn: &mut Raw, line: &str) {
+        use crate::client::{crypto::pwd_hash, utils::seal_line};
+        use tokio::io::AsyncWriteExt;
+        let key = pwd_hash(crate::server::config::DEFAULT_PASSWORD);
+        conn.get_mut().write_all(&seal_line(&key, line.to_string())).await.unwrap();
+    }
+
+    /// 读一行并解密；`ERR` 等明文原样返回，连接关闭时返回空串
+    async fn raw_recv(conn: &mut Raw) -> String {
+        use crate::client::crypto::{aead_open, pwd_hash};
+        use tokio::io::AsyncBufReadExt;
+        let key = pwd_hash(crate::server::config::DEFAULT_PASSWORD);
+        let mut line = String::new();
+        tokio::time::timeout(std::time::Duration::from_secs(5), conn.read_line(&mut line)).await.expect("timed out").unwrap();
+        aead_open(&key, line.trim_end()).unwrap_or_else(|| line.trim_end().to_string())
+    }
+
+    /// 一直读到满足条件的一行
+    async fn raw_until(conn: &mut Raw, pred: impl Fn(&str) -> bool) -> String {
+        loop {
+            let line = raw_recv(conn).await;
+            assert!(!line.is_empty(), "connection closed");
+            if pred(&line) {
+                return line;
+            }
+        }
+    }
+
+    /// 手工完成 AUTH 并发送进房指令，返回 ROOMS 横幅、服务器的回复与连接
+    async fn raw_enter(addr: std::net::SocketAddr, cmd: &str) -> (String, String, Raw) {
+        use crate::client::crypto::enc_auth;
+        let mut conn = tokio::io::BufReader::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        raw_send(&mut conn, &format!("AUTH {}", enc_auth(crate::server::config::DEFAULT_PASSWORD))).await;
+        assert_eq!(raw_recv(&mut conn).await, "OK");
+        let banner = raw_recv(&mut conn).await;
+        raw_send(&mut conn, cmd).await;
+        let reply = raw_recv(&mut conn).await;
+        (banner, reply, conn)
+    }
+
+    #[tokio::test]
+    async fn test_client_identity_namespace() {
+        use crate::client::{addr::ServerAddr, crypto::room_secrets, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+
+        let server = local_server().await;
+        let raw = server.local_addrs()[0];
+        let addr = ServerAddr::new("127.0.0.1", raw.port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        // 旧客户端（不带身份）建房，房主记为 ip:127.0.0.1
+        let (_, cred) = room_secrets("legacy", "pw");
+        let (_, reply, _owner) = raw_enter(raw, &format!("CREATE legacy {cred} old")).await;
+        assert_eq!(reply, "OK");
+        // 自报 ip: 身份冒充它会被拒绝
+        let (_, reply, _) = raw_enter(raw, &format!("JOIN legacy {cred} fake ip:127.0.0.1")).await;
+        assert_eq!(reply, "ERR BadIdentity");
+        // 带身份的客户端即使来自同一 IP 也只是普通成员，管不了旧客户端的房主
+        let (mallory, mut events) = Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "mallory").with_identity([9; 32])).await.unwrap();
+        mallory.join("legacy", "pw").await.unwrap();
+        mallory.moderate("legacy", "kick old").await.unwrap();
+        let err = until(&mut events, |e| matches!(e, ClientEvent::Error { .. })).await;
+        assert!(matches!(err, ClientEvent::Error { why, .. } if why == "NotPermitted"));
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_registry_survives_restart() {
+        use crate::server::{config::ServerConfig, Server};
+
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = ServerConfig { state: Some(dir.path().join("state.json")), ..ServerConfig::default() };
+        let start = |cfg: ServerConfig| async move {
+            let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+            let addr = server.local_addrs()[0];
+            let shutdown = server.shutdown_handle();
+            (addr, shutdown, tokio::spawn(server.run()))
+        };
+
+        let (addr, shutdown, running) = start(cfg.clone()).await;
+        let (_, reply, mut owner) = raw_enter(addr, "CREATE keep cred owner 0wner").await;
+        assert_eq!(reply, "OK");
+        for setting in ["persist on", "capacity 3", "visibility hidden", "retention 10 1h", "topic SEALED-TOPIC"] {
+            raw_send(&mut owner, &format!("/room keep {setting}")).await;
+        }
+        raw_until(&mut owner, |l| l == "#keep /topic owner SEALED-TOPIC").await;
+        drop(owner);
+        shutdown.shutdown();
+        running.await.unwrap().unwrap();
+
+        let (addr, shutdown, _running) = start(cfg).await;
+        // 没人在线的持久房间也还在：别人既不能用另一个密码抢建，也看不到它（隐藏）
+        let (banner, reply, _) = raw_enter(addr, "CREATE keep other mallory m4llory").await;
+        assert_eq!(reply, "ERR RoomExists");
+        assert!(!banner.split_whitespace().any(|r| r == "keep"), "{banner}");
+        let (_, reply, _) = raw_enter(addr, "JOIN keep other mallory m4llory").await;
+        assert_eq!(reply, "ERR BadCredential");
+
+        let (_, reply, mut owner) = raw_enter(addr, "JOIN keep cred owner 0wner").await;
+        assert_eq!(reply, "OK");
+        raw_until(&mut owner, |l| l == "#keep /topic - SEALED-TOPIC").await;
+        raw_send(&mut owner, "/room keep").await;
+        let info = raw_until(&mut owner, |l| l.starts_with("#keep /room_info")).await;
+        assert_eq!(info, "#keep /room_info persist=on capacity=3 visibility=hidden retention=10/3600s topic=SEALED-TOPIC");
+        // 房主身份也恢复了：只有房主能改容量
+        raw_send(&mut owner, "/room keep capacity 4").await;
+        raw_until(&mut owner, |l| l == "#keep /room_set capacity owner 4").await;
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_hidden_rooms_not_listed() {
+        let server = local_server().await;
+        let addr = server.local_addrs()[0];
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (_, reply, _listed) = raw_enter(addr, "CREATE lobby cred a 1d").await;
+        assert_eq!(reply, "OK");
+        let (_, reply, _hidden) = raw_enter(addr, "CREATE hideout cred b 2d hidden").await;
+        assert_eq!(reply, "OK");
+        let (banner, _, _) = raw_enter(addr, "JOIN hideout cred c 3d").await;
+        let rooms: Vec<_> = banner.split_whitespace().filter(|w| !w.starts_with('+')).collect();
+        assert_eq!(rooms, ["ROOMS", "lobby"]);
+        shutdown.shutdown();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hashed_rooms_never_reach_the_server() {
+        use crate::client::{addr::ServerAddr, crypto::{room_hash, room_secrets}, Client, ClientEvent, Credentials};
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("admin.sock");
+        let cfg = ServerConfig { hashed_rooms: true, admin_socket: Some(socket.clone()), ..ServerConfig::default() };
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let raw = server.local_addrs()[0];
+        let addr = ServerAddr::new("127.0.0.1", raw.port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (alice, mut events) = Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "alice")).await.unwrap();
+        alice.join("secret-plans", "pw").await.unwrap();
+        alice.join("second-room", "pw").await.unwrap();
+        until(&mut events, |e| matches!(e, ClientEvent::Joined { room } if room == "second-room")).await;
+        // 房间表里只有房间哈希；横幅也不列出任何房间
+        let rooms = admin(&socket, "rooms").await;
+        assert!(!rooms.contains("secret-plans") && !rooms.contains("second-room"), "{rooms}");
+        for name in ["secret-plans", "second-room"] {
+            assert!(rooms.contains(&room_hash(&room_secrets(name, "pw").0)), "{rooms}");
+        }
+        // 明文房间号直接被拒绝
+        let (banner, reply, _) = raw_enter(raw, "ENTER plain cred bob b0b").await;
+        assert_eq!(reply, "ERR HashedRoomsOnly");
+        assert!(banner.ends_with("+hashed"), "{banner}");
+        shutdown.shutdown();
+    }
+
+    /// 发一行 AUTH（`password` 为 None 时发不可解的垃圾），返回服务器的第一行回复
+    async fn try_auth(addr: std::net::SocketAddr, password: Option<&str>) -> String {
+        use crate::client::crypto::enc_auth;
+        let mut conn = tokio::io::BufReader::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        match password {
+            Some(pwd) => raw_send(&mut conn, &format!("AUTH {}", enc_auth(pwd))).await,
+            None => tokio::io::AsyncWriteExt::write_all(conn.get_mut(), b"AUTH nope\n").await.unwrap(),
+        }
+        raw_recv(&mut conn).await
+    }
+
+    #[tokio::test]
+    async fn test_governor_lockout_and_per_ip_cap() {
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+        use tokio::io::AsyncBufReadExt;
+
+        let mut cfg = ServerConfig::default();
+        cfg.limits.lockout_after = 2;
+        cfg.limits.max_per_ip = 2;
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = server.local_addrs()[0];
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        // 同一 IP 同时最多两个连接，第三个直接被拒
+        let held = [
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+        ];
+        let mut third = tokio::io::BufReader::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        let mut line = String::new();
+        third.read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim_end(), "ERR TooManyConnections");
+        drop(held);
+
+        // 连续失败两次后锁定，正确的口令也进不来；名额在服务器读到断开后才归还
+        let first = loop {
+            match try_auth(addr, None).await {
+                refused if refused == "ERR TooManyConnections" => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+                reply => break reply,
+            }
+        };
+        assert_eq!(first, "ERR BadAuth");
+        assert_eq!(try_auth(addr, None).await, "ERR BadAuth");
+        let locked = try_auth(addr, Some(DEFAULT_PASSWORD)).await;
+        assert!(locked.starts_with("ERR Locked "), "{locked}");
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let cfg = ServerConfig { metrics_bind: Some("127.0.0.1:0".into()), ..ServerConfig::default() };
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = server.local_addrs()[0];
+        let metrics = server.metrics_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        // 一次口令错误，一条转发的消息
+        assert_eq!(try_auth(addr, None).await, "ERR BadAuth");
+        let addr = ServerAddr::new("127.0.0.1", addr.port());
+        let (alice, _alice_events) = Client::connect(addr.clone(), Credentials::new(DEFAULT_PASSWORD, "alice")).await.unwrap();
+        alice.join("metered", "pw").await.unwrap();
+        let (bob, mut bob_events) = Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "bob")).await.unwrap();
+        bob.join("metered", "pw").await.unwrap();
+        alice.send_text("metered", "counted").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Message { text, .. } if text == "counted")).await;
+
+        let mut conn = tokio::net::TcpStream::connect(metrics).await.unwrap();
+        conn.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        let lines: Vec<&str> = response.lines().collect();
+        assert!(lines.contains(&"rust_chat_auth_failures_total 1"), "{response}");
+        assert!(lines.contains(&"rust_chat_messages_relayed_total 1"), "{response}");
+        assert!(lines.contains(&"rust_chat_room_members{room=\"metered\"} 2"), "{response}");
+        // 只有通过的 AUTH 计入握手耗时，两个客户端都落在 +Inf 桶里
+        assert!(lines.contains(&"rust_chat_handshake_seconds_bucket{stage=\"auth\",le=\"+Inf\"} 2"), "{response}");
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_never_records_messages() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::{config::DEFAULT_PASSWORD, logging::audit_layer};
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::prelude::*;
+
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        // current_thread 运行时，服务器的任务都在本线程上跑，线程级的 subscriber 就够了
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let writer = captured.clone();
+        let _guard = tracing_subscriber::registry().with(audit_layer(move || Capture(writer.clone()))).set_default();
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        let creds = |nick: &str, id: u8| Credentials::new(DEFAULT_PASSWORD, nick).with_identity([id; 32]);
+        let (alice, _alice_events) = Client::connect(addr.clone(), creds("alice", 1)).await.unwrap();
+        alice.join("audited", "pw").await.unwrap();
+        let (bob, mut bob_events) = Client::connect(addr, creds("bob", 2)).await.unwrap();
+        bob.join("audited", "pw").await.unwrap();
+        alice.send_text("audited", "top secret plan").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Message { text, .. } if text == "top secret plan")).await;
+        alice.moderate("audited", "kick bob").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Kicked { ban: false, .. })).await;
+        shutdown.shutdown();
+
+        let log = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+        // 明文和密文（`ENC:` 开头）都不进审计日志
+        assert!(!log.contains("top secret"), "{log}");
+        assert!(!log.contains("ENC:"), "{log}");
+        let kick = log
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .find(|e| e["event"] == "moderation" && e["action"] == "kick")
+            .unwrap_or_else(|| panic!("no kick event in {log}"));
+        assert_eq!(kick["room"], "audited");
+        assert_eq!(kick["target"], "bob");
+        assert_eq!(kick["by"], "alice");
+    }
+
+    #[tokio::test]
+    async fn test_cli_exit_codes() {
+        use crate::client::{addr::ServerAddr, cli::{self, ConnectArgs, Exit, Format, ListenArgs, SendArgs}, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        // 口令走文件，不碰进程级的环境变量
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, text: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, text).unwrap();
+            Some(path)
+        };
+        let (right, wrong, room_pwd) = (file("right", DEFAULT_PASSWORD), file("wrong", "not-the-password"), file("room", "pw"));
+        let conn = |server: &ServerAddr, password: &Option<std::path::PathBuf>, room: &str| ConnectArgs {
+            server: server.clone(),
+            room: room.into(),
+            nick: Some("cli".into()),
+            server_password_file: password.clone(),
+            room_password_file: room_pwd.clone(),
+        };
+        let send = |conn| cli::send(SendArgs { conn, message: Some("hi".into()), image: None });
+        let listen = |conn| cli::listen(ListenArgs { conn, format: Format::Text, count: Some(1), no_history: false });
+
+        // 本机 IP 在这个房间被封禁，CLI 进不去
+        let creds = |nick: &str, id: u8| Credentials::new(DEFAULT_PASSWORD, nick).with_identity([id; 32]);
+        let (owner, _owner_events) = Client::connect(addr.clone(), creds("owner", 1)).await.unwrap();
+        owner.join("banned", "pw").await.unwrap();
+        let (bob, mut bob_events) = Client::connect(addr.clone(), creds("bob", 2)).await.unwrap();
+        bob.join("banned", "pw").await.unwrap();
+        owner.moderate("banned", "ban bob").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Kicked { ban: true, .. })).await;
+        assert_eq!(send(conn(&addr, &right, "banned")).await, Exit::Room);
+        assert_eq!(listen(conn(&addr, &right, "banned")).await, Exit::Room);
+        // 口令错误放在最后：失败过的 IP 之后的 AUTH 会被限速
+        assert_eq!(send(conn(&addr, &wrong, "lobby")).await, Exit::Auth);
+        assert_eq!(listen(conn(&addr, &wrong, "lobby")).await, Exit::Auth);
+        shutdown.shutdown();
+
+        // 只接受连接、从不应答的服务器：两个子命令都在握手超时后退出
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_addr = ServerAddr::new("127.0.0.1", silent.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = silent.accept().await {
+                held.push(socket);
+            }
+        });
+        let (sent, listened) = tokio::join!(
+            send(conn(&silent_addr, &right, "lobby")),
+            listen(conn(&silent_addr, &right, "lobby")),
+        );
+        assert_eq!((sent, listened), (Exit::Timeout, Exit::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_bot_runner_leaves_after_last_kick() {
+        use crate::client::{addr::ServerAddr, bot::{Bot, BotRunner, Command, Context}, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+        use std::time::Duration;
+
+        struct Pong;
+        impl Bot for Pong {
+            fn on_command(&mut self, ctx: &mut Context, _sender: &str, cmd: &Command) {
+                if cmd.name == "ping" {
+                    ctx.reply("pong");
+                }
+            }
+        }
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        let creds = |nick: &str, id: u8| Credentials::new(DEFAULT_PASSWORD, nick).with_identity([id; 32]);
+
+        let (owner, mut owner_events) = Client::connect(addr.clone(), creds("owner", 1)).await.unwrap();
+        owner.join("first", "pw").await.unwrap();
+        owner.join("second", "pw").await.unwrap();
+        until(&mut owner_events, |e| matches!(e, ClientEvent::Joined { room } if room == "second")).await;
+        // 同一房间配置两次：按次数计数时踢完两个房间也不会退出
+        let runner = BotRunner::new(addr, creds("pongbot", 2)).room("first", "pw").room("first", "pw").room("second", "pw");
+        let mut bot = tokio::spawn(async move { runner.run(&mut Pong).await });
+        for room in ["first", "second"] {
+            until(&mut owner_events, |e| matches!(e, ClientEvent::UserJoined { room: r, nick } if r == room && nick == "pongbot")).await;
+        }
+
+        // 踢出一个房间后还在另一个房间里应答
+        owner.moderate("first", "kick pongbot").await.unwrap();
+        owner.send_text("second", "!ping").await.unwrap();
+        until(&mut owner_events, |e| matches!(e, ClientEvent::Message { room, text, .. } if room == "second" && text == "pong")).await;
+        assert!(tokio::time::timeout(Duration::from_millis(200), &mut bot).await.is_err());
+
+        owner.moderate("second", "kick pongbot").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), bot).await.unwrap().unwrap().unwrap();
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_flood_escalates_to_mute_then_kick() {
+        use crate::server::{config::ServerConfig, Server};
+        use std::time::Duration;
+
+        let mut cfg = ServerConfig::default();
+        cfg.flood.msgs_per_sec = 20.0;
+        cfg.flood.msg_burst = 1;
+        cfg.flood.mute_after = 2;
+        cfg.flood.mute_secs = 1;
+        cfg.flood.kick_after = 3;
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = server.local_addrs()[0];
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (_, reply, mut conn) = raw_enter(addr, "CREATE noisy cred flooder f100d").await;
+        assert_eq!(reply, "OK");
+        // 每段连续超限记一次违规：第一次警告，第二次禁言，禁言结束后第三次踢出
+        async fn burst(conn: &mut Raw) {
+            raw_send(conn, "#noisy one").await;
+            raw_send(conn, "#noisy two").await;
+        }
+        burst(&mut conn).await;
+        raw_until(&mut conn, |l| l == "#noisy /slow_down 1").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        burst(&mut conn).await;
+        raw_until(&mut conn, |l| l == "#noisy /mod mute flooder server 1").await;
+        raw_send(&mut conn, "#noisy still here").await;
+        raw_until(&mut conn, |l| l == "#noisy /mod_err Muted").await;
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        burst(&mut conn).await;
+        // 踢出的广播与退订同时发生，本连接只保证收到 /kicked
+        raw_until(&mut conn, |l| l == "#noisy /kicked server kick").await;
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_lagged_receiver_gets_backlog() {
+        use crate::server::{config::ServerConfig, Server};
+
+        let mut cfg = ServerConfig::default();
+        cfg.backpressure.channel_capacity = 2;
+        cfg.rooms.history = 50;
+        cfg.flood.msg_burst = 100;
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = server.local_addrs()[0];
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let (_, _, mut sender) = raw_enter(addr, "CREATE busy cred talker 7a1c").await;
+        let (_, reply, mut slow) = raw_enter(addr, "JOIN busy cred slow 510w").await;
+        assert_eq!(reply, "OK");
+        raw_until(&mut slow, |l| l == "#busy /member_list talker,slow" || l == "#busy /member_list slow,talker").await;
+        // 一口气发完：发送方的循环不让出，广播缓冲只有 2 条，接收方必然落后
+        for n in 0..20 {
+            raw_send(&mut sender, &format!("#busy m{n}")).await;
+        }
+        // 收到 /lagged，丢掉的从历史补发（/history），其余照常转发：每条恰好一次、顺序不变
+        let (mut got, mut missed) = (Vec::new(), 0);
+        while got.len() < 20 {
+            let line = raw_recv(&mut slow).await;
+            assert!(!line.is_empty(), "connection closed");
+            let line = line.strip_prefix("#busy ").unwrap();
+            if let Some(n) = line.strip_prefix("/lagged ") {
+                missed = n.parse().unwrap();
+                continue;
+            }
+            let line = match line.strip_prefix("/history ") {
+                Some(rest) => rest.split_once(' ').unwrap().1,
+                None => line,
+            };
+            if let Some(body) = line.strip_prefix("[talker] ") {
+                got.push(body.to_string());
+            }
+        }
+        assert!(missed > 0);
+        let expected: Vec<_> = (0..20).map(|n| format!("m{n}")).collect();
+        assert_eq!(got, expected);
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_countdown_and_drain() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+        use std::time::{Duration, Instant};
+
+        let start = |grace: u64| async move {
+            let cfg = ServerConfig { shutdown_grace_secs: grace, ..ServerConfig::default() };
+            let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+            let addr = server.local_addrs()[0];
+            let shutdown = server.shutdown_handle();
+            (addr, shutdown, tokio::spawn(server.run()))
+        };
+
+        // 所有人都断开后立即返回，不必等满宽限期
+        let (raw, shutdown, running) = start(30).await;
+        let (alice, mut events) = Client::connect(ServerAddr::new("127.0.0.1", raw.port()), Credentials::new(DEFAULT_PASSWORD, "alice")).await.unwrap();
+        alice.join("lobby", "pw").await.unwrap();
+        until(&mut events, |e| matches!(e, ClientEvent::Joined { .. })).await;
+        shutdown.shutdown();
+        let notice = until(&mut events, |e| matches!(e, ClientEvent::Shutdown { .. })).await;
+        assert!(matches!(notice, ClientEvent::Shutdown { secs } if secs > 20 && secs <= 30));
+        alice.quit().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), running).await.expect("drain waited for the grace period").unwrap().unwrap();
+
+        // 不肯走的连接在宽限期到点时被断开
+        let (raw, shutdown, running) = start(1).await;
+        let (_, reply, mut stubborn) = raw_enter(raw, "CREATE lobby cred bob b0b").await;
+        assert_eq!(reply, "OK");
+        let began = Instant::now();
+        shutdown.shutdown();
+        raw_until(&mut stubborn, |l| l == "/shutdown 0" || l == "/shutdown 1").await;
+        tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+        assert!(began.elapsed() >= Duration::from_millis(900));
+        loop {
+            if raw_recv(&mut stubborn).await.is_empty() {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mute_rejects_oversized_duration() {
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        let creds = |nick: &str, id: u8| Credentials::new(DEFAULT_PASSWORD, nick).with_identity([id; 32]);
+
+        let (owner, mut owner_events) = Client::connect(addr.clone(), creds("owner", 1)).await.unwrap();
+        owner.join("quiet", "pw").await.unwrap();
+        let (bob, mut bob_events) = Client::connect(addr.clone(), creds("bob", 2)).await.unwrap();
+        bob.join("quiet", "pw").await.unwrap();
+        until(&mut owner_events, |e| matches!(e, ClientEvent::UserJoined { nick, .. } if nick == "bob")).await;
+
+        // 溢出 Instant 的时长与超过一年的时长都被拒绝，服务器照常工作
+        for huge in ["18446744073709551615s", "99999999999d", "366d"] {
+            owner.moderate("quiet", &format!("mute bob {huge}")).await.unwrap();
+            let err = until(&mut owner_events, |e| matches!(e, ClientEvent::Error { .. })).await;
+            assert!(matches!(err, ClientEvent::Error { why, .. } if why == "BadDuration"));
+        }
+        owner.moderate("quiet", "mute bob 1d").await.unwrap();
+        bob.send_text("quiet", "hello?").await.unwrap();
+        let err = until(&mut bob_events, |e| matches!(e, ClientEvent::Error { .. })).await;
+        assert!(matches!(err, ClientEvent::Error { why, .. } if why == "Muted"));
+        let (carol, _) = Client::connect(addr, creds("carol", 3)).await.unwrap();
+        carol.join("quiet", "pw").await.unwrap();
+        shutdown.shutdown();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_identity_survives_password_rotation() {
+        use crate::client::{addr::ServerAddr, crypto::pwd_hash, Client, ClientEvent, Credentials};
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+
+        let dir = tempfile::tempdir().unwrap();
+        let password_file = dir.path().join("password");
+        std::fs::write(&password_file, hex::encode(pwd_hash(DEFAULT_PASSWORD))).unwrap();
+        let socket = dir.path().join("admin.sock");
+        let cfg = ServerConfig {
+            password_file: Some(password_file),
+            admin_socket: Some(socket.clone()),
+            password_rotation_secs: 60,
+            ..ServerConfig::default()
+        };
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        let creds = |pwd: &str, nick: &str, id: u8| Credentials::new(pwd, nick).with_identity([id; 32]);
+
+        let (owner, _owner_events) = Client::connect(addr.clone(), creds(DEFAULT_PASSWORD, "owner", 7)).await.unwrap();
+        owner.join("rotating", "pw").await.unwrap();
+
+        assert!(admin(&socket, "rotate-password fresh-password").await.starts_with("OK"));
+        // 轮换窗口内新旧口令的客户端进的是同一个房间，房主换了新口令仍是房主
+        let (bob, mut bob_events) = Client::connect(addr.clone(), creds(DEFAULT_PASSWORD, "bob", 8)).await.unwrap();
+        bob.join("rotating", "pw").await.unwrap();
+        let (owner2, _owner2_events) = Client::connect(addr, creds("fresh-password", "owner2", 7)).await.unwrap();
+        owner2.join("rotating", "pw").await.unwrap();
+        owner2.moderate("rotating", "kick bob").await.unwrap();
+        until(&mut bob_events, |e| matches!(e, ClientEvent::Kicked { ban: false, .. })).await;
+        shutdown.shutdown();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rotated_password_survives_reload() {
+        use crate::client::{addr::ServerAddr, crypto::pwd_hash, Client, Credentials};
+        use crate::server::{config::{ServerConfig, DEFAULT_PASSWORD}, Server};
+
+        // 没有口令文件：拒绝轮换，否则下一次 reload 就会换回去
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("plain.sock");
+        let cfg = ServerConfig { admin_socket: Some(socket.clone()), ..ServerConfig::default() };
+        let _plain = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let reply = admin(&socket, "rotate-password fresh-password").await;
+        assert!(reply.starts_with("ERR rotate-password needs password_file"), "{reply}");
+
+        // 有口令文件：写回文件，reload 读到的也是新口令
+        let password_file = dir.path().join("password");
+        std::fs::write(&password_file, hex::encode(pwd_hash(DEFAULT_PASSWORD))).unwrap();
+        let socket = dir.path().join("file.sock");
+        let cfg = ServerConfig {
+            password_file: Some(password_file),
+            admin_socket: Some(socket.clone()),
+            password_rotation_secs: 0,
+            ..ServerConfig::default()
+        };
+        let server = Server::builder().config(cfg).bind("127.0.0.1:0").build().await.unwrap();
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        assert!(admin(&socket, "rotate-password fresh-password").await.starts_with("OK"));
+        assert_eq!(admin(&socket, "reload").await.lines().next(), Some("OK"));
+        Client::connect(addr.clone(), Credentials::new("fresh-password", "alice")).await.unwrap();
+        assert!(Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "bob")).await.is_err());
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_room_command_seals_pins() {
+        use crate::client::{crypto::open, keyboard::room_command, tabs::RoomTab};
+        let tab = RoomTab::new("lobby".into(), "pw".into());
+        for input in ["/room pin hello  world", "/pin hello  world", "/room topic hello  world"] {
+            let args = room_command(input, &tab).unwrap();
+            let (key, sealed) = args.split_once(' ').unwrap();
+            assert!(key == "pin" || key == "topic");
+            // 服务器按空格拆分置顶列表，密文里不能有空格
+            assert!(!sealed.contains(' '), "{args}");
+            assert_eq!(open(&tab.key, sealed).as_deref(), Some("hello  world"));
+        }
+        assert_eq!(room_command("/room capacity 5", &tab).as_deref(), Some("capacity 5"));
+    }
+
+    #[test]
+    fn test_bot_command_parse() {
+        use crate::client::bot::parse_command;
+        let cmd = parse_command("!", "  !roll 2d6  fast ").unwrap();
+        assert_eq!((cmd.name, cmd.rest, cmd.args), ("roll", "2d6  fast", vec!["2d6", "fast"]));
+        assert_eq!(parse_command("!", "!help").unwrap().args, Vec::<&str>::new());
+        assert!(parse_command("!", "! roll").is_none());
+        assert!(parse_command("!", "hello !roll").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_post() {
+        use crate::client::webhook::{Webhook, WebhookConfig};
+        use crate::client::{addr::ServerAddr, Client, ClientEvent, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+        use futures_util::StreamExt;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = local_server().await;
+        let port = server.local_addrs()[0].port();
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+
+        let cfg: WebhookConfig = toml::from_str(&format!(r#"
+            server = "127.0.0.1:{port}"
+            bind = "127.0.0.1:0"
+            [[room]]
+            name = "ops"
+            password = "pw"
+            [[token]]
+            name = "ci"
+            token = "0123456789abcdef"
+            rooms = ["ops"]
+        "#)).unwrap();
+        let webhook = Webhook::start(cfg, DEFAULT_PASSWORD.to_string()).await.unwrap();
+        let http = webhook.local_addr().unwrap();
+        tokio::spawn(webhook.run());
+
+        let (alice, mut events) = Client::connect(ServerAddr::new("127.0.0.1", port), Credentials::new(DEFAULT_PASSWORD, "alice")).await.unwrap();
+        alice.join("ops", "pw").await.unwrap();
+
+        async fn post(addr: std::net::SocketAddr, token: &str, body: &str) -> String {
+            let mut conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let req = format!("POST /message HTTP/1.1\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+            conn.write_all(req.as_bytes()).await.unwrap();
+            let mut reply = String::new();
+            conn.read_to_string(&mut reply).await.unwrap();
+            reply.lines().next().unwrap_or("").to_string()
+        }
+        assert!(post(http, "wrong-token-wrong", r#"{"room":"ops","text":"x"}"#).await.contains("401"));
+        assert!(post(http, "0123456789abcdef", r#"{"room":"dev","text":"x"}"#).await.contains("403"));
+        assert!(post(http, "0123456789abcdef", "not json").await.contains("400"));
+        assert!(post(http, "0123456789abcdef", r#"{"room":"ops","text":"deploy done"}"#).await.contains("200"));
+
+        let msg = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let ClientEvent::Message { sender, text, .. } = events.next().await.unwrap() {
+                    return (sender, text);
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(msg, ("webhook".to_string(), "deploy done".to_string()));
+        shutdown.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_irc_gateway() {
+        use crate::client::irc::Gateway;
+        use crate::client::{addr::ServerAddr, Client, Credentials};
+        use crate::server::config::DEFAULT_PASSWORD;
+        use std::time::Duration;
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let server = local_server().await;
+        let addr = ServerAddr::new("127.0.0.1", server.local_addrs()[0].port());
+        let shutdown = server.shutdown_handle();
+        tokio::spawn(server.run());
+        let gateway = Gateway::bind("127.0.0.1:0", addr.clone(), Some(DEFAULT_PASSWORD.to_string())).await.unwrap();
+        let irc = gateway.local_addr().unwrap();
+        tokio::spawn(gateway.run());
+
+        let (reader, mut writer) = tokio::net::TcpStream::connect(irc).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        // 读到包含 `want` 的一行
+        let mut until = async |want: &str| {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    let line = lines.next_line().await.unwrap().expect("closed");
+                    if line.contains(want) {
+                        return line;
+                    }
+                }
+            })
+            .await
+            .expect(want)
+        };
+        writer.write_all(b"NICK alice\r\nUSER alice 0 * :Alice\r\n").await.unwrap();
+        until(" 001 alice ").await;
+        writer.write_all(b"JOIN #ops pw\r\n").await.unwrap();
+        until(":alice!alice@rust_chat JOIN #ops").await;
+        assert!(until(" 353 ").await.ends_with("#ops :alice"));
+
+        let (bob, _events) = Client::connect(addr, Credentials::new(DEFAULT_PASSWORD, "bob")).await.unwrap();
+        bob.join("ops", "pw").await.unwrap();
+        until(":bob!bob@rust_chat JOIN #ops").await;
+        bob.send_text("ops", "hello irc").await.unwrap();
+        assert!(until("PRIVMSG").await.ends_with("PRIVMSG #ops :hello irc"));
+        writer.write_all(b"QUIT\r\n").await.unwrap();
+        until("ERROR").await;
+        shutdown.shutdown();
+    }
 }
@@ -0,0 +1,241 @@
+// server/admin.rs
+//! 本机管理接口：Unix 套接字（权限 0600），配合 `chat-admin` 使用
+//!
+//! 每个连接发一行命令，服务器回复若干行后关闭：首行 `OK` 或 `ERR <原因>`，其余为内容。
+use std::{
+    collections::HashSet,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::client::crypto::pwd_hash;
+
+use super::{config, logging, moderate, Control, Reload, ServerState, State};
+
+/// 管理命令的说明，`help` 返回
+const HELP: &str = "\
+rooms                      list rooms
+members <room>             list members of a room
+stats                      connection and room counters
+kick <room> <nick>         kick a member out of a room
+ban <room> <nick>          ban a member's identity and IP from a room
+announce <text>            send a notice to every connected client
+rotate-password <new>      use a new server password (needs password_file); the old one keeps working for password_rotation_secs
+end-rotation               stop accepting the previous server password now
+reload                     re-read the config file and environment";
+
+/// 绑定套接字并在后台处理管理命令
+pub fn spawn(path: &Path, state: State, reload: Reload) -> Result<()> {
+    // 上次没有正常退出时会残留套接字文件
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    tracing::info!(path = %path.display(), "admin socket listening");
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            let (state, reload) = (state.clone(), reload.clone());
+            tokio::spawn(async move {
+                if let Err(e) = session(stream, state, reload).await {
+                    tracing::warn!("admin connection error: {e:#}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn session(stream: UnixStream, state: State, reload: Reload) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let Some(line) = BufReader::new(reader).lines().next_line().await? else { return Ok(()) };
+    let reply = match run(line.trim(), &state, &reload) {
+        Ok(body) if body.is_empty() => "OK\n".to_string(),
+        Ok(body) => format!("OK\n{body}\n"),
+        Err(e) => format!("ERR {e:#}\n"),
+    };
+    writer.write_all(reply.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+fn run(line: &str, state: &ServerState, reload: &Reload) -> Result<String> {
+    let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+    // 口令轮换的参数不进日志
+    audit!(event = "admin", command = cmd, args = if cmd == "rotate-password" { "" } else { args });
+    match cmd {
+        "help" => Ok(HELP.to_string()),
+        "rooms" => Ok(list_rooms(state)),
+        "members" => list_members(state, args),
+        "stats" => Ok(stats(state)),
+        "kick" | "ban" => {
+            let (room, nick) = args.split_once(' ').ok_or_else(|| anyhow!("usage: {cmd} <room> <nick>"))?;
+            moderate(state, room, None, "server", &format!("{cmd} {}", nick.trim())).map_err(|why| anyhow!(why))?;
+            Ok(String::new())
+        }
+        "announce" => {
+            if args.is_empty() {
+                bail!("usage: announce <text>");
+            }
+            Ok(format!("delivered to {} connection(s)", announce(state, args)))
+        }
+        "rotate-password" => rotate(state, args),
+        "end-rotation" => {
+            if !state.expire_previous_key() {
+                bail!("no password rotation in progress");
+            }
+            audit!(event = "password_rotation_ended", via = "admin");
+            Ok("previous password no longer accepted".into())
+        }
+        "reload" => apply_reload(state, reload),
+        _ => bail!("unknown command {cmd:?}, try `help`"),
+    }
+}
+
+fn list_rooms(state: &ServerState) -> String {
+    let map = state.rooms.lock().unwrap();
+    let mut ids: Vec<_> = map.keys().collect();
+    ids.sort();
+    ids.iter()
+        .map(|id| {
+            let info = &map[*id];
+            let mut line = format!("{id} members={} visibility={:?}", info.members.len(), info.settings.visibility);
+            if info.persistent {
+                line.push_str(" persistent");
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn list_members(state: &ServerState, room: &str) -> Result<String> {
+    let map = state.rooms.lock().unwrap();
+    let info = map.get(room).ok_or_else(|| anyhow!("NoSuchRoom"))?;
+    let mut members: Vec<_> = info.members.iter().collect();
+    members.sort_by_key(|(id, _)| **id);
+    Ok(members
+        .iter()
+        .map(|(id, m)| {
+            let role = match info.rank(&m.identity) {
+                2 => " owner",
+                1 => " mod",
+                _ => "",
+            };
+            format!("{id} {} {}{role}", m.nickname, m.identity)
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn stats(state: &ServerState) -> String {
+    let map = state.rooms.lock().unwrap();
+    let in_rooms: HashSet<u64> = map.values().flat_map(|info| info.members.keys().copied()).collect();
+    let persistent = map.values().filter(|info| info.persistent).count();
+    let uptime = state.started.elapsed().as_secs();
+    let mut lines = vec![
+        format!("connections={}", state.governor.connections()),
+        format!("tracked_ips={}", state.governor.peers()),
+        format!("rooms={} persistent={persistent}", map.len()),
+        format!("members={}", in_rooms.len()),
+        format!("uptime={}h{:02}m{:02}s", uptime / 3600, uptime / 60 % 60, uptime % 60),
+    ];
+    if let Some(left) = state.previous_key_remaining() {
+        lines.push(format!("previous_password_expires_in={}s", left.as_secs()));
+    }
+    lines.join("\n")
+}
+
+/// 给每个在房间里的连接发一次公告，返回连接数
+fn announce(state: &ServerState, text: &str) -> usize {
+    let map = state.rooms.lock().unwrap();
+    let mut sent = HashSet::new();
+    for info in map.values() {
+        for (id, m) in &info.members {
+            if sent.insert(*id) {
+                let _ = m.ctl.send(Control::Announce { text: text.to_string() });
+            }
+        }
+    }
+    sent.len()
+}
+
+/// 新握手改用新口令，旧口令在轮换窗口内仍可登录；已建立的连接继续用各自的密钥
+///
+/// 只支持 `password_file`：新口令的哈希写回文件，`reload` 与重启读到的都是它。
+/// 口令写在配置文件或环境变量里时服务器改不了来源，只在内存里换的话下一次 `reload` 就会换回去，
+/// 所以直接拒绝，请改好配置后 `reload`。
+fn rotate(state: &ServerState, password: &str) -> Result<String> {
+    if password.is_empty() {
+        bail!("usage: rotate-password <new password>");
+    }
+    if password == config::DEFAULT_PASSWORD {
+        bail!("refusing to rotate to the default password");
+    }
+    let key = pwd_hash(password);
+    if key == state.server_key() {
+        bail!("the new password is the same as the current one");
+    }
+    let (file, window) = {
+        let cfg = state.config();
+        (cfg.password_file.clone(), cfg.password_rotation_secs)
+    };
+    let Some(path) = file else {
+        bail!("rotate-password needs password_file; without it change the password in the config or RUST_CHAT_PASSWORD and run `reload`");
+    };
+    std::fs::write(&path, format!("{}\n", hex::encode(key))).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+    let mut note = format!("password rotated, {} updated", path.display());
+    state.rotate_key(key, Duration::from_secs(window));
+    audit!(event = "password_rotated", via = "admin", window_secs = window);
+    note.push('\n');
+    note.push_str(&window_note(window));
+    Ok(note)
+}
+
+/// 重新加载配置；监听地址与状态文件需要重启才能生效
+fn apply_reload(state: &ServerState, reload: &Reload) -> Result<String> {
+    let cfg = reload()?;
+    let key = cfg.password_hash()?;
+    let mut notes = Vec::new();
+    {
+        let old = state.config();
+        if cfg.bind != old.bind {
+            notes.push("bind changed: restart to apply".to_string());
+        }
+        if cfg.state != old.state {
+            notes.push("state changed: restart to apply".to_string());
+        }
+        if cfg.admin_socket != old.admin_socket {
+            notes.push("admin_socket changed: restart to apply".to_string());
+        }
+    }
+    let window = cfg.password_rotation_secs;
+    let rotated = key != state.server_key();
+    logging::set_level(cfg.log_level);
+    *state.config.write().unwrap() = cfg;
+    if rotated {
+        state.rotate_key(key, Duration::from_secs(window));
+        notes.push("server password changed: new handshakes use the new password".into());
+        notes.push(window_note(window));
+        audit!(event = "password_rotated", via = "reload", window_secs = window);
+    }
+    tracing::info!("config reloaded");
+    Ok(notes.join("\n"))
+}
+
+fn window_note(window: u64) -> String {
+    if window == 0 {
+        "the previous password is no longer accepted".into()
+    } else {
+        format!("the previous password is accepted for {window}s (end early with `end-rotation`)")
+    }
+}
@@ -0,0 +1,303 @@
+// server/config.rs
+//! 服务器配置：`--config server.toml`
+//!
+//! 优先级：内置默认值 < 配置文件 < 环境变量 `RUST_CHAT_<KEY>`（嵌套键用 `__`，如
+//! `RUST_CHAT_ROOMS__MAX_MEMBERS`）< 命令行参数。
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::client::crypto::pwd_hash;
+use crate::client::frame::DEFAULT_MAX_FRAME;
+
+use super::registry::{parse_duration, Retention};
+
+/// 公开在 README 里的默认口令，只允许配合 `--insecure` 使用
+pub const DEFAULT_PASSWORD: &str = "Vrepol";
+
+const ENV_PREFIX: &str = "RUST_CHAT_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// 房间相关的全局上限与默认值
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomLimits {
+    /// 房间总数上限，0 不限
+    pub max_rooms:       usize,
+    /// 单个房间成员上限（房主设置的 capacity 不能突破它），0 不限
+    pub max_members:     usize,
+    /// 新房间默认保留的历史条数，0 不保留
+    pub history:         usize,
+    /// 新房间历史的最长保留时间，如 `24h`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_max_age: Option<String>,
+}
+
+impl RoomLimits {
+    pub fn retention(&self) -> Retention {
+        Retention {
+            keep:    self.history,
+            max_age: self.history_max_age.as_deref().and_then(parse_duration),
+        }
+    }
+}
+
+/// 连接守卫参数（见 governor.rs）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnLimits {
+    /// 单个 IP 同时连接数上限，0 不限
+    pub max_per_ip:       usize,
+    /// AUTH 令牌桶容量
+    pub auth_burst:       u32,
+    /// 每隔多少秒回填一个 AUTH 令牌
+    pub auth_refill_secs: u64,
+    /// 连续失败多少次后锁定，0 不锁定
+    pub lockout_after:    u32,
+    /// 首次锁定秒数，之后每次翻倍
+    pub lockout_secs:     u64,
+    pub lockout_max_secs: u64,
+}
+
+impl Default for ConnLimits {
+    fn default() -> Self {
+        Self {
+            max_per_ip:       16,
+            auth_burst:       5,
+            auth_refill_secs: 12,
+            lockout_after:    5,
+            lockout_secs:     60,
+            lockout_max_secs: 3600,
+        }
+    }
+}
+
+/// 刷屏控制（见 flood.rs），每个连接独立计算，0 表示不限
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FloodLimits {
+    pub msgs_per_sec:  f64,
+    pub msg_burst:     u32,
+    pub bytes_per_sec: usize,
+    pub bytes_burst:   usize,
+    /// 违规多少次自动禁言
+    pub mute_after:    u32,
+    pub mute_secs:     u64,
+    /// 违规多少次踢出房间
+    pub kick_after:    u32,
+    /// 多久没有违规后清零
+    pub forgive_secs:  u64,
+}
+
+impl Default for FloodLimits {
+    fn default() -> Self {
+        Self {
+            msgs_per_sec:  5.0,
+            msg_burst:     10,
+            bytes_per_sec: 512 * 1024,
+            // 至少放得下一张图片
+            bytes_burst:   16 * 1024 * 1024,
+            mute_after:    3,
+            mute_secs:     60,
+            kick_after:    6,
+            forgive_secs:  60,
+        }
+    }
+}
+
+/// 客户端跟不上房间广播时怎么办
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowConsumer {
+    /// 丢弃，告诉客户端丢了几条，并从房间历史补发还能找回的
+    Drop,
+    /// 直接断开
+    Disconnect,
+    /// 连接队列不设上限（占用内存换不丢消息）
+    Buffer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backpressure {
+    /// 每个房间的广播缓冲条数
+    pub channel_capacity: usize,
+    /// 每个连接待发送的帧数上限（buffer 策略下不限）
+    pub client_queue:     usize,
+    pub slow_consumer:    SlowConsumer,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Self { channel_capacity: 500, client_queue: 256, slow_consumer: SlowConsumer::Drop }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// 监听地址，可多个；未带端口时用 6655
+    pub bind:              Vec<String>,
+    /// 服务器口令明文（与 password_file 二选一）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password:          Option<String>,
+    /// 口令哈希文件，内容为 sha256(口令) 的十六进制，服务器不保存明文
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_file:     Option<PathBuf>,
+    /// 轮换口令后旧口令仍可用于新握手的秒数，0 立即失效
+    pub password_rotation_secs: u64,
+    /// 同时在线连接上限，0 不限
+    pub max_clients:       usize,
+    /// 单条消息（去掉服务器层加密后）的最大字节数
+    pub max_message_bytes: usize,
+    /// 单帧（一行密文）的最大字节数，超出即断开；握手时告知客户端
+    pub max_frame_bytes:   usize,
+    pub log_level:         LogLevel,
+    /// stderr 日志格式：text / json
+    pub log_format:        LogFormat,
+    /// 审计日志（追加写入的 JSON 行），不设则只进普通日志
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log:         Option<PathBuf>,
+    /// 持久房间状态文件，不设则不支持持久房间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state:             Option<PathBuf>,
+    /// 欢迎信息，可多行
+    pub motd:              String,
+    /// 只接受房间号哈希
+    pub hashed_rooms:      bool,
+    pub rooms:             RoomLimits,
+    pub limits:            ConnLimits,
+    pub flood:             FloodLimits,
+    pub backpressure:      Backpressure,
+    /// 收到 SIGINT/SIGTERM 后等待客户端断开的秒数
+    pub shutdown_grace_secs: u64,
+    /// 本机管理套接字（Unix），不设则不开启
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_socket:      Option<PathBuf>,
+    /// Prometheus 指标的 HTTP 监听地址，如 `127.0.0.1:9655`，不设则不开启
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_bind:      Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind:              vec!["0.0.0.0:6655".into()],
+            password:          None,
+            password_file:     None,
+            password_rotation_secs: 3600,
+            max_clients:       1024,
+            // 8 MiB 图片经 base64 与两层加密后约 11 MiB
+            max_message_bytes: 16 * 1024 * 1024,
+            max_frame_bytes:   DEFAULT_MAX_FRAME,
+            log_level:         LogLevel::Info,
+            log_format:        LogFormat::Text,
+            audit_log:         None,
+            state:             None,
+            motd:              String::new(),
+            hashed_rooms:      false,
+            rooms:             RoomLimits::default(),
+            limits:            ConnLimits::default(),
+            flood:             FloodLimits::default(),
+            backpressure:      Backpressure::default(),
+            shutdown_grace_secs: 10,
+            admin_socket:      None,
+            metrics_bind:      None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 服务器密钥 sha256(口令)；都没配置时为默认口令
+    pub fn password_hash(&self) -> Result<[u8; 32]> {
+        if let Some(path) = &self.password_file {
+            let text = fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+            let bytes = hex::decode(text.trim()).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+            return <[u8; 32]>::try_from(bytes)
+                .map_err(|_| anyhow!("{}: expected a 64-digit hex sha256", path.display()));
+        }
+        Ok(pwd_hash(self.password.as_deref().unwrap_or(DEFAULT_PASSWORD)))
+    }
+
+    pub fn uses_default_password(&self) -> Result<bool> {
+        Ok(self.password_hash()? == pwd_hash(DEFAULT_PASSWORD))
+    }
+}
+
+/// 读取配置文件（可选）并叠加环境变量
+pub fn load(path: Option<&Path>) -> Result<ServerConfig> {
+    let mut table = Table::try_from(ServerConfig::default())?;
+    if let Some(path) = path {
+        let text = fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        let file: Table = toml::from_str(&text).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        merge(&mut table, file);
+    }
+    for (name, raw) in std::env::vars() {
+        if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+            set_env(&mut table, &key.to_lowercase(), &raw).map_err(|e| anyhow!("{name}: {e}"))?;
+        }
+    }
+    let cfg: ServerConfig = Value::Table(table).try_into()?;
+    if let Some(age) = &cfg.rooms.history_max_age {
+        if parse_duration(age).is_none() {
+            bail!("rooms.history_max_age: bad duration {age:?}");
+        }
+    }
+    Ok(cfg)
+}
+
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(b)), Value::Table(o)) => merge(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// 按默认值的类型解析环境变量：字符串原样、数组按逗号拆分、其余按 TOML 字面量
+fn set_env(table: &mut Table, key: &str, raw: &str) -> Result<()> {
+    let mut path: Vec<&str> = key.split("__").collect();
+    let leaf = path.pop().unwrap_or_default();
+    let mut node = table;
+    for part in path {
+        node = match node.entry(part).or_insert_with(|| Value::Table(Table::new())) {
+            Value::Table(t) => t,
+            _ => bail!("{part} is not a table"),
+        };
+    }
+    let value = match node.get(leaf) {
+        Some(Value::Array(_)) => Value::Array(
+            raw.split(',').map(|s| Value::String(s.trim().to_string())).collect(),
+        ),
+        Some(Value::String(_)) | None => Value::String(raw.to_string()),
+        Some(_) => toml::from_str::<Table>(&format!("v = {raw}"))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .ok_or_else(|| anyhow!("cannot parse {raw:?}"))?,
+    };
+    node.insert(leaf.to_string(), value);
+    Ok(())
+}
@@ -0,0 +1,140 @@
+// server/flood.rs
+//! 刷屏控制：每个连接一组令牌桶（条数 + 字节），房间可另设更严的条数限制
+//!
+//! 超限的消息直接丢弃；每段连续超限只警告一次并记一次违规，
+//! 违规累计到 `mute_after` 自动禁言，到 `kick_after` 踢出房间，`forgive_secs` 内没有违规则清零。
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::config::FloodLimits;
+use super::registry::Rate;
+
+/// 令牌桶：容量 `capacity`，每秒回填 `per_sec`
+pub struct TokenBucket {
+    capacity: f64,
+    per_sec:  f64,
+    tokens:   f64,
+    at:       Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, per_sec: f64) -> Self {
+        TokenBucket { capacity, per_sec, tokens: capacity, at: Instant::now() }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let gained = now.duration_since(self.at).as_secs_f64() * self.per_sec;
+        self.tokens = (self.tokens + gained).min(self.capacity);
+        self.at = now;
+    }
+
+    /// 是否够 `cost`；超过容量的单次开销按容量算，否则永远发不出去
+    fn has(&mut self, cost: f64) -> bool {
+        self.refill();
+        self.tokens >= cost.min(self.capacity)
+    }
+
+    fn take(&mut self, cost: f64) {
+        self.tokens -= cost.min(self.capacity);
+    }
+}
+
+/// 一条消息的处理结果
+pub enum Verdict {
+    Pass,
+    /// 丢弃，本段超限已经警告过
+    Drop,
+    /// 丢弃并警告，附带违规次数
+    Warn(u32),
+    /// 丢弃并禁言若干秒
+    Mute(u64),
+    Kick,
+}
+
+pub struct FloodGuard {
+    limits:      FloodLimits,
+    msgs:        Option<TokenBucket>,
+    bytes:       Option<TokenBucket>,
+    /// 房间自己的条数限制（按设置变化重建）
+    rooms:       HashMap<String, (Rate, TokenBucket)>,
+    strikes:     u32,
+    last_strike: Option<Instant>,
+    /// 当前这段超限是否已经警告过
+    warned:      bool,
+}
+
+/// 没有桶表示不限
+fn has(bucket: Option<&mut TokenBucket>, cost: f64) -> bool {
+    match bucket {
+        Some(b) => b.has(cost),
+        None => true,
+    }
+}
+
+fn bucket(burst: f64, per_sec: f64) -> Option<TokenBucket> {
+    (per_sec > 0.0).then(|| TokenBucket::new(burst.max(1.0), per_sec))
+}
+
+impl FloodGuard {
+    pub fn new(limits: FloodLimits) -> Self {
+        FloodGuard {
+            msgs: bucket(limits.msg_burst as f64, limits.msgs_per_sec),
+            bytes: bucket(limits.bytes_burst as f64, limits.bytes_per_sec as f64),
+            limits,
+            rooms: HashMap::new(),
+            strikes: 0,
+            last_strike: None,
+            warned: false,
+        }
+    }
+
+    /// 在 `room` 发一条 `len` 字节的消息；`rate` 为房间设置
+    pub fn check(&mut self, room: &str, rate: Option<Rate>, len: usize) -> Verdict {
+        let mut room_bucket = rate.map(|rate| {
+            let entry = self.rooms
+                .entry(room.to_string())
+                .or_insert_with(|| (rate, TokenBucket::new(rate.burst.max(1) as f64, rate.per_sec)));
+            if entry.0 != rate {
+                *entry = (rate, TokenBucket::new(rate.burst.max(1) as f64, rate.per_sec));
+            }
+            &mut entry.1
+        });
+        let len = len as f64;
+        let ok = has(self.msgs.as_mut(), 1.0) && has(self.bytes.as_mut(), len) && has(room_bucket.as_deref_mut(), 1.0);
+        if ok {
+            for (b, cost) in [(self.msgs.as_mut(), 1.0), (self.bytes.as_mut(), len), (room_bucket, 1.0)] {
+                if let Some(b) = b {
+                    b.take(cost);
+                }
+            }
+            self.warned = false;
+            return Verdict::Pass;
+        }
+        if self.warned {
+            return Verdict::Drop;
+        }
+        self.warned = true;
+        let forgive = Duration::from_secs(self.limits.forgive_secs);
+        if self.last_strike.is_some_and(|at| at.elapsed() > forgive) {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = Some(Instant::now());
+        let limits = &self.limits;
+        if limits.kick_after > 0 && self.strikes >= limits.kick_after {
+            self.strikes = 0;
+            Verdict::Kick
+        } else if limits.mute_after > 0 && self.strikes == limits.mute_after {
+            Verdict::Mute(limits.mute_secs)
+        } else {
+            Verdict::Warn(self.strikes)
+        }
+    }
+
+    pub fn forget(&mut self, room: &str) {
+        self.rooms.remove(room);
+    }
+}
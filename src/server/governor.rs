@@ -0,0 +1,187 @@
+// server/governor.rs
+//! 连接守卫：全局连接上限、单 IP 并发上限、AUTH 令牌桶限速与失败锁定
+//!
+//! 每个 IP 一个令牌桶，每次 AUTH 消耗一个令牌，按 `auth_refill_secs` 回填；
+//! 连续失败 `lockout_after` 次后锁定，锁定时长随锁定次数翻倍，封顶 `lockout_max_secs`。
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{config, ServerState};
+
+/// 一个 `Server` 的连接计数与各 IP 的限速状态
+#[derive(Default)]
+pub struct Governor {
+    /// 当前在线连接数
+    clients: AtomicUsize,
+    peers:   Mutex<HashMap<IpAddr, Peer>>,
+}
+
+struct Peer {
+    conns:        usize,
+    tokens:       f64,
+    refilled:     Instant,
+    /// 连续失败次数（成功后清零）
+    failures:     u32,
+    /// 已被锁定的次数，决定下一次锁定时长
+    strikes:      u32,
+    locked_until: Option<Instant>,
+    last_failure: Option<Instant>,
+}
+
+impl Peer {
+    fn new(burst: u32) -> Self {
+        Peer {
+            conns:        0,
+            tokens:       burst as f64,
+            refilled:     Instant::now(),
+            failures:     0,
+            strikes:      0,
+            locked_until: None,
+            last_failure: None,
+        }
+    }
+
+    fn refill(&mut self, limits: &config::ConnLimits) {
+        let now = Instant::now();
+        if limits.auth_refill_secs > 0 {
+            let gained = now.duration_since(self.refilled).as_secs_f64() / limits.auth_refill_secs as f64;
+            self.tokens = (self.tokens + gained).min(limits.auth_burst as f64);
+        } else {
+            self.tokens = limits.auth_burst as f64;
+        }
+        self.refilled = now;
+        // 很久没有失败：锁定次数清零
+        if self.last_failure.is_some_and(|at| at.elapsed() > Duration::from_secs(limits.lockout_max_secs)) {
+            self.strikes = 0;
+            self.last_failure = None;
+        }
+    }
+
+    /// 没有连接、没有锁定、桶已满：可以从表里删掉
+    fn idle(&self, limits: &config::ConnLimits) -> bool {
+        self.conns == 0
+            && self.failures == 0
+            && self.strikes == 0
+            && !matches!(self.locked_until, Some(t) if t > Instant::now())
+            && self.tokens >= limits.auth_burst as f64
+    }
+}
+
+/// 一个已放行的连接，drop 时归还名额
+pub struct Permit {
+    state: Arc<ServerState>,
+    ip:    IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let limits = self.state.config().limits.clone();
+        self.state.governor.release(self.ip, &limits);
+    }
+}
+
+/// 新连接准入：超过全局或单 IP 上限时返回拒绝原因
+pub fn admit(state: &Arc<ServerState>, ip: IpAddr) -> Result<Permit, &'static str> {
+    let (max_clients, limits) = {
+        let cfg = state.config();
+        (cfg.max_clients, cfg.limits.clone())
+    };
+    state.governor.admit(ip, max_clients, &limits)?;
+    Ok(Permit { state: state.clone(), ip })
+}
+
+impl Governor {
+    /// 当前在线连接数（关停时等它归零）
+    pub fn connections(&self) -> usize {
+        self.clients.load(Ordering::SeqCst)
+    }
+
+    /// 当前记录在案的 IP 数（在线或有失败记录）
+    pub fn peers(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    fn admit(&self, ip: IpAddr, max_clients: usize, limits: &config::ConnLimits) -> Result<(), &'static str> {
+        let mut peers = self.peers.lock().unwrap();
+        if max_clients > 0 && self.clients.load(Ordering::SeqCst) >= max_clients {
+            return Err("ServerFull");
+        }
+        let peer = peers.entry(ip).or_insert_with(|| Peer::new(limits.auth_burst));
+        if limits.max_per_ip > 0 && peer.conns >= limits.max_per_ip {
+            return Err("TooManyConnections");
+        }
+        peer.conns += 1;
+        self.clients.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn release(&self, ip: IpAddr, limits: &config::ConnLimits) {
+        self.clients.fetch_sub(1, Ordering::SeqCst);
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(peer) = peers.get_mut(&ip) {
+            peer.conns = peer.conns.saturating_sub(1);
+            peer.refill(limits);
+            if peer.idle(limits) {
+                peers.remove(&ip);
+            }
+        }
+    }
+
+    /// AUTH 前调用：锁定中或令牌用完时拒绝
+    pub fn check_auth(&self, ip: IpAddr, limits: &config::ConnLimits) -> Result<(), String> {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.entry(ip).or_insert_with(|| Peer::new(limits.auth_burst));
+        peer.refill(limits);
+        if let Some(until) = peer.locked_until {
+            let now = Instant::now();
+            if until > now {
+                return Err(format!("Locked {}", (until - now).as_secs().max(1)));
+            }
+            peer.locked_until = None;
+        }
+        if peer.tokens < 1.0 {
+            return Err("Throttled".into());
+        }
+        peer.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// 记录 AUTH 结果；失败累计到阈值时锁定并写日志
+    pub fn auth_result(&self, ip: IpAddr, ok: bool, limits: &config::ConnLimits) {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(peer) = peers.get_mut(&ip) else { return };
+        if ok {
+            peer.failures = 0;
+            peer.strikes = 0;
+            peer.last_failure = None;
+            return;
+        }
+        peer.failures += 1;
+        peer.last_failure = Some(Instant::now());
+        if limits.lockout_after > 0 && peer.failures >= limits.lockout_after {
+            let secs = limits
+                .lockout_secs
+                .saturating_mul(1 << peer.strikes.min(20))
+                .min(limits.lockout_max_secs);
+            peer.failures = 0;
+            peer.strikes += 1;
+            peer.locked_until = Some(Instant::now() + Duration::from_secs(secs));
+            audit!(event = "auth_lockout", peer = %ip, secs, strikes = peer.strikes);
+        }
+    }
+
+    /// 定期清理空闲条目，避免扫描器留下的大量 IP 常驻内存
+    pub fn sweep(&self, limits: &config::ConnLimits) {
+        self.peers.lock().unwrap().retain(|_, peer| {
+            peer.refill(limits);
+            !peer.idle(limits)
+        });
+    }
+}
@@ -0,0 +1,76 @@
+// server/logging.rs
+//! 日志：tracing 输出到 stderr（`log_format = "text" | "json"`），每个连接一个 span（peer、nick、room）
+//!
+//! 审计事件用 `audit!` 记录（target 为 `audit`），除了进普通日志，还会以 JSON 行追加到 `audit_log`。
+//! 审计事件只记录谁、在哪、做了什么，从不记录消息内容。
+use std::{
+    fs::OpenOptions,
+    sync::{atomic::{AtomicU8, Ordering}, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::{filter::filter_fn, fmt, fmt::MakeWriter, prelude::*, registry::LookupSpan, Layer};
+
+use super::config::{LogFormat, LogLevel, ServerConfig};
+
+pub const AUDIT: &str = "audit";
+
+/// subscriber 是进程级的，级别也只有一份；`reload` 时由管理接口更新
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+pub(crate) fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// 按当前级别过滤；span 总是保留，否则低级别时事件会丢掉连接信息
+fn enabled(level: &Level) -> bool {
+    // 与 LogLevel 的顺序一致
+    const LEVELS: [Level; 4] = [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG];
+    *level <= LEVELS[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+/// 安装全局 subscriber；级别可由 `reload` 修改，立即生效
+pub fn init(cfg: &ServerConfig) -> Result<()> {
+    set_level(cfg.log_level);
+    let level = filter_fn(|meta| meta.is_span() || enabled(meta.level()));
+    let stderr = fmt::layer().with_writer(std::io::stderr);
+    let stderr = match cfg.log_format {
+        LogFormat::Text => stderr.with_target(false).boxed(),
+        LogFormat::Json => stderr.json().with_current_span(true).with_span_list(false).boxed(),
+    };
+    let audit = match &cfg.audit_log {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+            Some(audit_layer(Mutex::new(file)))
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr.with_filter(level))
+        .with(audit)
+        .try_init()
+        .map_err(|e| anyhow!("logging: {e}"))
+}
+
+/// 审计层：只收 `audit` target 的事件，每个一行 JSON，带上连接 span 的字段
+///
+/// 不输出 target：它总是 `audit`，还会和管理事件的 `target` 字段（被操作的昵称）重名
+pub(crate) fn audit_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    fmt::layer()
+        .json()
+        .with_target(false)
+        .flatten_event(true)
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_writer(writer)
+        .with_filter(filter_fn(|meta| meta.is_span() || meta.target() == AUDIT))
+}
@@ -0,0 +1,183 @@
+// server/metrics.rs
+//! Prometheus 文本格式的运行指标：`metrics_bind` 上的 `GET /metrics`
+//!
+//! 计数器属于各自的 `Server`，在各处直接累加；在线连接、房间与成员数在抓取时从房间表现算。
+//! 不依赖 HTTP 框架：每个请求读完请求头、回一个响应就关闭连接。
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::client::addr::ServerAddr;
+
+use super::ServerState;
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// 桶上界（秒），最后隐含一个 +Inf
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+pub struct Histogram {
+    /// 每个桶各自计数，输出时再累加成 Prometheus 的累计形式
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_us:  AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let idx = BUCKETS.iter().position(|&le| secs <= le).unwrap_or(BUCKETS.len());
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// `labels` 形如 `stage="auth"`，与 `le` 一起输出
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut total = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            total += bucket.load(Ordering::Relaxed);
+            let le = BUCKETS.get(i).map_or("+Inf".to_string(), f64::to_string);
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{le}\"}} {total}");
+        }
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}\n{name}_count{{{labels}}} {total}");
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    pub connections_accepted: Counter,
+    pub connections_rejected: Counter,
+    pub auth_failures:        Counter,
+    pub messages_relayed:     Counter,
+    pub bytes_relayed:        Counter,
+    pub lagged_receivers:     Counter,
+    pub lagged_messages:      Counter,
+    pub panics:               Counter,
+    /// 握手耗时，不含用户在终端输入房间号的时间：
+    /// auth 从连接建立到 AUTH 通过，enter 从收到进房指令到回复 OK
+    pub handshake_auth:       Histogram,
+    pub handshake_enter:      Histogram,
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}");
+}
+
+/// 标签值里的 `\`、`"` 和换行要转义
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// 当前全部指标（Prometheus 文本格式 0.0.4）
+pub fn render(state: &ServerState) -> String {
+    let m = &state.metrics;
+    let mut out = String::new();
+    gauge(&mut out, "rust_chat_connections", "Open client connections.", state.governor.connections());
+    {
+        let map = state.rooms.lock().unwrap();
+        gauge(&mut out, "rust_chat_rooms", "Rooms currently on the server.", map.len());
+        let _ = writeln!(out, "# HELP rust_chat_room_members Members per room.\n# TYPE rust_chat_room_members gauge");
+        let mut ids: Vec<_> = map.keys().collect();
+        ids.sort();
+        for id in ids {
+            let _ = writeln!(out, "rust_chat_room_members{{room=\"{}\"}} {}", label(id), map[id].members.len());
+        }
+    }
+    let counters = [
+        ("rust_chat_connections_accepted_total", "Connections admitted by the governor.", &m.connections_accepted),
+        ("rust_chat_connections_rejected_total", "Connections refused for connection limits.", &m.connections_rejected),
+        ("rust_chat_auth_failures_total", "Failed AUTH attempts.", &m.auth_failures),
+        ("rust_chat_messages_relayed_total", "Chat messages relayed to rooms.", &m.messages_relayed),
+        ("rust_chat_bytes_relayed_total", "Bytes of chat messages relayed to rooms.", &m.bytes_relayed),
+        ("rust_chat_lagged_receivers_total", "Times a connection fell behind a room broadcast.", &m.lagged_receivers),
+        ("rust_chat_lagged_messages_total", "Broadcast messages skipped by lagging connections.", &m.lagged_messages),
+        ("rust_chat_panics_total", "Connection task panics caught.", &m.panics),
+    ];
+    for (name, help, c) in counters {
+        counter(&mut out, name, help, c.get());
+    }
+    let name = "rust_chat_handshake_seconds";
+    let _ = writeln!(out, "# HELP {name} Handshake latency by stage (auth, enter).\n# TYPE {name} histogram");
+    m.handshake_auth.render(&mut out, name, "stage=\"auth\"");
+    m.handshake_enter.render(&mut out, name, "stage=\"enter\"");
+    out
+}
+
+/// 未带端口时的默认指标端口
+const DEFAULT_METRICS_PORT: u16 = 9655;
+
+/// 绑定指标端口并在后台应答，返回实际监听的地址
+pub async fn spawn(bind: &str, state: Arc<ServerState>) -> Result<SocketAddr> {
+    let addr = ServerAddr::parse_with_port(bind, DEFAULT_METRICS_PORT)?;
+    let listener = addr.bind().await.map_err(|e| anyhow!("metrics {addr}: {e}"))?;
+    let local = listener.local_addr()?;
+    tracing::info!(addr = %local, "metrics listening on /metrics");
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            let state = state.clone();
+            tokio::spawn(async move {
+                let _ = tokio::time::timeout(Duration::from_secs(10), respond(stream, &state)).await;
+            });
+        }
+    });
+    Ok(local)
+}
+
+async fn respond(mut stream: TcpStream, state: &ServerState) -> std::io::Result<()> {
+    // 只需要请求行；请求头读到空行为止，最多 8 KiB
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8192 {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let mut parts = request.split_whitespace();
+    let path = parts.nth(1).map(|p| p.split('?').next().unwrap_or(p));
+    let (status, body) = match (request.split_whitespace().next(), path) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(state)),
+        (Some("GET"), Some(_)) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,1315 @@
+// server/mod.rs
+//! 聊天服务器：房间表、握手与每个连接的聊天循环
+//!
+//! [`Server::builder`] 配置监听地址与 [`ServerConfig`]，`build()` 绑定端口，`run()` 一直服务到
+//! [`ShutdownHandle::shutdown`]。房间表、配置、服务器口令、连接守卫、持久化与运行指标都放在
+//! 每个 `Server` 自己的 [`ServerState`] 里，同一进程里的多个 `Server` 互不影响；
+//! 只有日志 subscriber 是进程级的。
+
+/// 审计事件（见 logging.rs）：只记录身份、房间与动作，从不记录消息内容
+macro_rules! audit {
+    ($($arg:tt)+) => {
+        tracing::info!(target: $crate::server::logging::AUDIT, $($arg)+)
+    };
+}
+
+#[cfg(unix)]
+mod admin;
+pub mod config;
+mod flood;
+mod governor;
+pub mod logging;
+mod metrics;
+mod registry;
+
+use anyhow::{bail, Result};
+use futures_util::FutureExt;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    panic::AssertUnwindSafe,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, RwLock, RwLockReadGuard},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpListener, TcpStream},
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+
+use tracing::{field::Empty, Instrument};
+use crate::client::crypto::{aead_open, dec_auth};
+use crate::client::utils::seal_line;
+use crate::client::addr::{ServerAddr, DEFAULT_PORT};
+use registry::{parse_duration, PersistedRoom, Registry, RoomSettings, Visibility};
+use config::{ServerConfig, SlowConsumer};
+use flood::{FloodGuard, Verdict};
+use governor::Governor;
+use metrics::Metrics;
+use crate::client::frame::{is_too_large, FrameReader, FRAME_TOO_LARGE};
+
+/// 认证前的单帧上限
+const HANDSHAKE_MAX_FRAME: usize = 64 * 1024;
+
+
+/// 服务器密钥 sha256(口令)：新握手优先用 current；轮换后旧密钥在窗口期内仍被接受
+struct ServerKeys {
+    current:  [u8; 32],
+    /// 旧密钥及其失效时刻
+    previous: Option<([u8; 32], Instant)>,
+}
+
+/// 一个 `Server` 的运行状态：各连接、管理接口与指标端口共用同一份
+struct ServerState {
+    rooms:    Mutex<HashMap<String, RoomInfo>>,
+    /// 当前配置；管理接口 `reload` 时整体替换
+    config:   RwLock<ServerConfig>,
+    keys:     RwLock<ServerKeys>,
+    governor: Governor,
+    registry: Registry,
+    metrics:  Metrics,
+    started:  Instant,
+}
+
+type State = Arc<ServerState>;
+
+impl ServerState {
+    /// 当前配置（只读）；不要在持有它时再读一次，reload 等写锁时会死锁
+    fn config(&self) -> RwLockReadGuard<'_, ServerConfig> {
+        self.config.read().unwrap()
+    }
+
+    fn server_key(&self) -> [u8; 32] {
+        self.keys.read().unwrap().current
+    }
+
+    /// 换成新密钥，旧密钥在 `window` 内仍可用于握手；已建立的会话不受影响
+    fn rotate_key(&self, key: [u8; 32], window: Duration) {
+        let mut keys = self.keys.write().unwrap();
+        let old = std::mem::replace(&mut keys.current, key);
+        keys.previous = (!window.is_zero() && old != key).then(|| (old, Instant::now() + window));
+    }
+
+    /// 提前结束轮换窗口，返回是否有旧密钥被作废
+    fn expire_previous_key(&self) -> bool {
+        self.keys.write().unwrap().previous.take().is_some_and(|(_, until)| until > Instant::now())
+    }
+
+    /// 旧密钥还剩多久失效
+    fn previous_key_remaining(&self) -> Option<Duration> {
+        let (_, until) = self.keys.read().unwrap().previous?;
+        until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())
+    }
+
+    /// 握手时可接受的密钥：当前密钥，以及未过期的旧密钥
+    fn accepted_keys(&self) -> Vec<[u8; 32]> {
+        let keys = self.keys.read().unwrap();
+        let previous = keys.previous.filter(|(_, until)| *until > Instant::now()).map(|(key, _)| key);
+        std::iter::once(keys.current).chain(previous).collect()
+    }
+}
+
+/// 关停时刻：开始平滑关停时设置，各连接据此通知客户端并在到点时断开
+type Deadline = watch::Receiver<Option<Instant>>;
+
+/// 发给某个连接的控制消息
+enum Control {
+    /// 被踢出/封禁：由它自己退订并通知客户端
+    Kicked { room: String, by: String, ban: bool },
+    /// 跟不上房间广播（slow_consumer = "disconnect"），断开连接
+    Lagged { room: String, missed: u64 },
+    /// 管理员公告，发往整个连接
+    Announce { text: String },
+}
+
+/// 房间广播：(聊天序号, 帧)；序号只给 relay 的聊天消息，其余事件为 0
+type Event = (u64, String);
+
+/// 连接编号：成员表以它为键，同名或改名都不会串号
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+struct Member {
+    nickname: String,
+    /// `key:<客户端身份标识>`，旧客户端为 `ip:<地址>`
+    identity: String,
+    /// 对端 IP：封禁时一并记录，删掉身份文件也绕不过
+    ip:       IpAddr,
+    ctl:      mpsc::UnboundedSender<Control>,
+}
+
+/// 房间广播的是服务器层明文（聊天正文仍是房间密钥密文），由各连接自行加 `#room` 前缀并加密
+struct RoomInfo {
+    tx: broadcast::Sender<Event>,
+    /// 最近一条聊天消息的序号；history 总是以它结尾的连续一段
+    seq: u64,
+    credential: String,
+    members: HashMap<u64, Member>,
+    /// 创建者身份；离开后回来仍是房主
+    owner: String,
+    mods: HashSet<String>,
+    /// 按身份与 `ip:<地址>` 封禁，换昵称或身份文件都无效
+    bans: HashSet<String>,
+    /// 身份 → 解禁时间（None 为永久）
+    muted: HashMap<String, Option<Instant>>,
+    /// 写入状态文件，没人时也保留
+    persistent: bool,
+    settings: RoomSettings,
+    /// 按 settings.retention 保留的近期消息（房间密钥密文）
+    history: VecDeque<(Instant, String)>,
+}
+
+impl RoomInfo {
+    fn new(credential: &str, owner: &str, cfg: &ServerConfig) -> Self {
+        let (tx, _) = broadcast::channel::<Event>(cfg.backpressure.channel_capacity.max(1));
+        Self {
+            tx,
+            seq: 0,
+            credential: credential.to_string(),
+            members: HashMap::new(),
+            owner: owner.to_string(),
+            mods: HashSet::new(),
+            bans: HashSet::new(),
+            muted: HashMap::new(),
+            persistent: false,
+            settings: RoomSettings { retention: cfg.rooms.retention(), ..RoomSettings::default() },
+            history: VecDeque::new(),
+        }
+    }
+
+    fn restore(room: PersistedRoom, cfg: &ServerConfig) -> Self {
+        Self {
+            mods: room.mods,
+            bans: room.bans,
+            persistent: true,
+            settings: room.settings,
+            ..Self::new(&room.credential, &room.owner, cfg)
+        }
+    }
+
+    fn persisted(&self, id: &str) -> PersistedRoom {
+        PersistedRoom {
+            id: id.to_string(),
+            credential: self.credential.clone(),
+            owner: self.owner.clone(),
+            mods: self.mods.clone(),
+            bans: self.bans.clone(),
+            settings: self.settings.clone(),
+        }
+    }
+
+    /// 记一条聊天消息并广播（调用方持锁，保证与新成员的历史快照不重不漏）
+    fn relay(&mut self, line: String) {
+        self.seq += 1;
+        if self.settings.retention.keep > 0 {
+            self.history.push_back((Instant::now(), line.clone()));
+            self.settings.retention.prune(&mut self.history);
+        }
+        let _ = self.tx.send((self.seq, line));
+    }
+
+    /// 广播不进历史的事件（加入离开、名单、管理操作等）
+    fn announce(&self, line: String) {
+        let _ = self.tx.send((0, line));
+    }
+
+    /// 历史里序号大于 `after` 的消息，转成 `/history` 帧
+    fn backlog_since(&mut self, after: u64) -> Vec<String> {
+        self.settings.retention.prune(&mut self.history);
+        let first = self.seq + 1 - self.history.len() as u64;
+        let now = chrono::Utc::now().timestamp();
+        self.history
+            .iter()
+            .zip(first..)
+            .filter(|(_, seq)| *seq > after)
+            .map(|((at, line), _)| format!("/history {} {line}", now - at.elapsed().as_secs() as i64))
+            .collect()
+    }
+
+    /// 0 普通成员 / 1 管理员 / 2 房主
+    fn rank(&self, identity: &str) -> u8 {
+        if self.owner == identity {
+            2
+        } else if self.mods.contains(identity) {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// 房主设置的 capacity 与服务器 max_members 取较小者
+    fn is_full(&self, max: usize) -> bool {
+        let cap = match (self.settings.capacity, max) {
+            (Some(cap), 0) => cap,
+            (Some(cap), max) => cap.min(max),
+            (None, 0) => return false,
+            (None, max) => max,
+        };
+        self.members.len() >= cap
+    }
+
+    /// 身份或 IP 被封禁；房主和管理员不受同一 IP 上封禁的影响
+    fn is_banned(&self, member: &Member) -> bool {
+        self.rank(&member.identity) == 0
+            && (self.bans.contains(&member.identity) || self.bans.contains(&format!("ip:{}", member.ip)))
+    }
+
+    fn nick_taken(&self, nickname: &str) -> bool {
+        self.members.values().any(|m| m.nickname == nickname)
+    }
+
+    /// 昵称被占用时加后缀 `alice_2`、`alice_3` …
+    fn free_nick(&self, nickname: &str) -> String {
+        if !self.nick_taken(nickname) {
+            return nickname.to_string();
+        }
+        (2..)
+            .map(|n| format!("{nickname}_{n}"))
+            .find(|candidate| !self.nick_taken(candidate))
+            .unwrap()
+    }
+
+    fn is_muted(&mut self, identity: &str) -> bool {
+        match self.muted.get(identity) {
+            Some(Some(until)) if Instant::now() >= *until => {
+                self.muted.remove(identity);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+/// 离开清理 guard：Drop 时发送离开消息并回收空房间
+struct RoomGuard {
+    state: State,
+    room_id: String,
+    conn_id: u64,
+}
+
+impl Drop for RoomGuard {
+    fn drop(&mut self) {
+        let mut map = self.state.rooms.lock().unwrap();
+        if let Some(info) = map.get_mut(&self.room_id) {
+            // 昵称可能已改过，以成员表里的为准
+            if let Some(member) = info.members.remove(&self.conn_id) {
+                audit!(event = "leave", room = %self.room_id, identity = %member.identity, nick = %member.nickname);
+                info.announce(format!("⚡ [{}] left.", member.nickname));
+            }
+                        broadcast_member_list(info);              // ← 推送最新名单
+                        // 回收空房间（持久房间保留）
+                        if info.members.is_empty() && !info.persistent {
+                            audit!(event = "room_destroy", room = %self.room_id);
+                            map.remove(&self.room_id);
+            }
+        }
+    }
+}
+/// 把所有持久房间写回状态文件（调用方持锁）
+fn save_registry(registry: &Registry, map: &HashMap<String, RoomInfo>) {
+    if !registry.enabled() {
+        return;
+    }
+    let rooms = map.iter()
+        .filter(|(_, info)| info.persistent)
+        .map(|(id, info)| info.persisted(id))
+        .collect();
+    if let Err(e) = registry.save(rooms) {
+        tracing::error!("failed to write the state file: {e:#}");
+    }
+}
+
+fn broadcast_member_list(info: &RoomInfo) {
+    let names: Vec<_> = info.members.values().map(|m| m.nickname.clone()).collect();
+    info.announce(format!("/member_list {}", names.join(",")));
+}
+
+/// 昵称会出现在 `[nick]` 前缀和逗号分隔的成员列表里
+fn valid_nick(nickname: &str) -> bool {
+    !nickname.is_empty()
+        && nickname.chars().count() <= 32
+        && !nickname.contains(|c: char| c.is_whitespace() || c == ',' || c == '[' || c == ']')
+}
+
+/// 房间号不能以 `+` 开头（ROOMS 横幅里 `+xxx` 是服务器能力标记）；哈希模式下必须是 64 位十六进制
+fn valid_room(room_id: &str, hashed_rooms: bool) -> Result<(), &'static str> {
+    if room_id.starts_with('+') {
+        return Err("BadRoom");
+    }
+    let is_hash = room_id.len() == 64 && room_id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    if hashed_rooms && !is_hash {
+        return Err("HashedRoomsOnly");
+    }
+    Ok(())
+}
+
+/// 校验并登记房间成员（无 await，持锁期间完成），返回广播端与实际使用的昵称
+/// action: CREATE / JOIN / ENTER（存在则加入，否则创建）；`hidden` 只在新建时生效
+///
+/// 昵称已被占用时：`free_nick` 为真（首个房间）则在同一把锁里加后缀，否则返回 `NickTaken`。
+#[allow(clippy::too_many_arguments)]
+fn enter_room(
+    state: &ServerState,
+    action: &str,
+    room_id: &str,
+    cred: &str,
+    conn_id: u64,
+    mut member: Member,
+    hidden: bool,
+    free_nick: bool,
+) -> Result<(broadcast::Sender<Event>, String), &'static str> {
+    let cfg = state.config().clone();
+    valid_room(room_id, cfg.hashed_rooms)?;
+    let mut map = state.rooms.lock().unwrap();
+    let exists = map.contains_key(room_id);
+    match (action, exists) {
+        ("CREATE", true) => Err("RoomExists"),
+        ("CREATE", false) | ("ENTER", false) => {
+            let max_rooms = cfg.rooms.max_rooms;
+            if max_rooms > 0 && map.len() >= max_rooms {
+                return Err("TooManyRooms");
+            }
+            let mut info = RoomInfo::new(cred, &member.identity, &cfg);
+            if hidden {
+                info.settings.visibility = Visibility::Hidden;
+            }
+            audit!(event = "room_create", room = room_id, identity = %member.identity, nick = %member.nickname, hidden);
+            let nickname = member.nickname.clone();
+            info.members.insert(conn_id, member);
+            let tx = info.tx.clone();
+            map.insert(room_id.to_string(), info);
+            Ok((tx, nickname))
+        }
+        ("JOIN", true) | ("ENTER", true) => {
+            let info = map.get_mut(room_id).unwrap();
+            if free_nick {
+                member.nickname = info.free_nick(&member.nickname);
+            }
+            let result = if info.credential != cred {
+                Err("BadCredential")
+            } else if info.is_banned(&member) {
+                Err("Banned")
+            } else if info.is_full(cfg.rooms.max_members) {
+                Err("RoomFull")
+            } else if info.nick_taken(&member.nickname) {
+                Err("NickTaken")
+            } else {
+                Ok((info.tx.clone(), member.nickname.clone()))
+            };
+            match result {
+                Ok(_) => audit!(event = "join", room = room_id, identity = %member.identity, nick = %member.nickname),
+                Err(why) => audit!(event = "join_denied", room = room_id, identity = %member.identity, reason = why),
+            }
+            if result.is_ok() {
+                info.members.insert(conn_id, member);
+            }
+            result
+        }
+        ("JOIN", false) => Err("NoSuchRoom"),
+        _ => Err("UnknownAction"),
+    }
+}
+
+/// `/nick <new>`：在本连接订阅的所有房间里同时改名，任何一个房间重名都拒绝
+fn rename(state: &ServerState, conn_id: u64, subs: &HashMap<String, Subscription>, old: &str, new: &str) -> Result<(), &'static str> {
+    if !valid_nick(new) {
+        return Err("BadNick");
+    }
+    let mut map = state.rooms.lock().unwrap();
+    if subs.keys().any(|room| map.get(room).is_some_and(|info| info.nick_taken(new))) {
+        return Err("NickTaken");
+    }
+    for room in subs.keys() {
+        if let Some(info) = map.get_mut(room) {
+            if let Some(member) = info.members.get_mut(&conn_id) {
+                member.nickname = new.to_string();
+            }
+            info.announce(format!("/renamed {old} {new}"));
+            broadcast_member_list(info);
+        }
+    }
+    Ok(())
+}
+
+/// 执行 `/mod <room> <kick|ban|mute|unmute|op> <nick> [时长]`
+///
+/// 房主可管所有人；管理员只能管普通成员；只有房主能任命管理员。
+/// 成功后向全房间广播 `/mod <action> <target> <by> [秒数]`。
+fn moderate(
+    state: &ServerState,
+    room_id: &str,
+    actor: Option<&str>,
+    actor_nick: &str,
+    args: &str,
+) -> Result<(), &'static str> {
+    let mut it = args.split_whitespace();
+    let action = it.next().unwrap_or_default();
+    let target = it.next().ok_or("MissingNick")?;
+    let mut map = state.rooms.lock().unwrap();
+    let info = map.get_mut(room_id).ok_or("NoSuchRoom")?;
+    let (target_id, target_ip) = info.members.values()
+        .find(|m| m.nickname == target)
+        .map(|m| (m.identity.clone(), m.ip))
+        .ok_or("NoSuchMember")?;
+    // actor 为 None 表示管理接口，权限高于房主
+    let (mine, theirs) = (actor.map_or(3, |a| info.rank(a)), info.rank(&target_id));
+    let allowed = match action {
+        "op" => mine >= 2 && theirs == 0,
+        "kick" | "ban" | "mute" | "unmute" => mine > theirs,
+        _ => return Err("UnknownCommand"),
+    };
+    if !allowed {
+        return Err("NotPermitted");
+    }
+
+    let mut event = format!("/mod {action} {target} {actor_nick}");
+    match action {
+        "op" => {
+            info.mods.insert(target_id.clone());
+        }
+        "mute" => {
+            let secs = match it.next() {
+                Some(d) => parse_duration(d).ok_or("BadDuration")?,
+                None => 0,
+            };
+            let until = match secs {
+                0 => None,
+                secs => Some(Instant::now().checked_add(Duration::from_secs(secs)).ok_or("BadDuration")?),
+            };
+            info.muted.insert(target_id.clone(), until);
+            event.push_str(&format!(" {secs}"));
+        }
+        "unmute" => {
+            info.muted.remove(&target_id);
+        }
+        _ => {
+            // kick / ban：通知目标连接自行退订（ban 同时记下身份与 IP，踢掉所有被它挡住的连接）
+            let ban = action == "ban";
+            if ban {
+                info.bans.insert(target_id.clone());
+                info.bans.insert(format!("ip:{target_ip}"));
+            }
+            for m in info.members.values() {
+                if m.nickname == target || (ban && info.is_banned(m)) {
+                    let _ = m.ctl.send(Control::Kicked { room: room_id.to_string(), by: actor_nick.to_string(), ban });
+                }
+            }
+        }
+    }
+    audit!(event = "moderation", action, room = room_id, target, identity = %target_id, by = actor_nick);
+    let changed = info.persistent && matches!(action, "op" | "ban");
+    info.announce(event);
+    if changed {
+        save_registry(&state.registry, &map);
+    }
+    Ok(())
+}
+
+/// 转发一条房间消息：通过禁言与刷屏检查才广播，返回要回给发送者的提示
+fn post(
+    state: &ServerState,
+    flood: &mut FloodGuard,
+    room: &str,
+    nickname: &str,
+    identity: &str,
+    ctl: &mpsc::UnboundedSender<Control>,
+    body: &str,
+) -> Option<String> {
+    let mut map = state.rooms.lock().unwrap();
+    let info = map.get_mut(room)?;
+    if info.is_muted(identity) {
+        return Some("/mod_err Muted".into());
+    }
+    match flood.check(room, info.settings.rate, body.len()) {
+        Verdict::Pass => {
+            let line = format!("[{nickname}] {body}");
+            state.metrics.messages_relayed.inc();
+            state.metrics.bytes_relayed.add(line.len() as u64);
+            info.relay(line);
+        }
+        Verdict::Drop => {}
+        Verdict::Warn(strikes) => return Some(format!("/slow_down {strikes}")),
+        Verdict::Mute(secs) => {
+            audit!(event = "moderation", action = "mute", room, target = nickname, identity, by = "server", reason = "flood", secs);
+            // 配置的时长大到溢出时按永久处理，不能在持锁时 panic
+            info.muted.insert(identity.to_string(), Instant::now().checked_add(Duration::from_secs(secs)));
+            info.announce(format!("/mod mute {nickname} server {secs}"));
+        }
+        Verdict::Kick => {
+            audit!(event = "moderation", action = "kick", room, target = nickname, identity, by = "server", reason = "flood");
+            info.announce(format!("/mod kick {nickname} server"));
+            let _ = ctl.send(Control::Kicked { room: room.to_string(), by: "server".into(), ban: false });
+        }
+    }
+    None
+}
+
+/// 每个房间最多置顶几条
+const MAX_PINS: usize = 10;
+
+/// `/pins <by> <密文>…`；by 为 `-` 表示只是同步状态（新成员加入时）
+fn pins_frame(by: &str, pins: &[String]) -> String {
+    let mut line = format!("/pins {by}");
+    for pin in pins {
+        line.push(' ');
+        line.push_str(pin);
+    }
+    line
+}
+
+/// 执行 `/room <room> [<setting> <value>]`
+///
+/// 不带参数时返回 `/room_info …` 给请求者。主题（`topic`）与置顶（`pin` / `unpin [n]`）
+/// 管理员即可修改，分别广播 `/topic <by> [密文]`、`/pins <by> …`；
+/// 其余设置仅房主可改，广播 `/room_set <setting> <by> <value>`。
+fn configure(
+    state: &ServerState,
+    room_id: &str,
+    actor: &str,
+    actor_nick: &str,
+    args: &str,
+) -> Result<Option<String>, &'static str> {
+    let mut map = state.rooms.lock().unwrap();
+    let info = map.get_mut(room_id).ok_or("NoSuchRoom")?;
+    let (key, value) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    if key.is_empty() {
+        let s = &info.settings;
+        let r = s.retention;
+        let mut line = format!(
+            "/room_info persist={} capacity={} visibility={} retention={}{}",
+            if info.persistent { "on" } else { "off" },
+            s.capacity.map_or("none".into(), |n| n.to_string()),
+            s.visibility.as_str(),
+            r.keep,
+            r.max_age.map_or(String::new(), |a| format!("/{a}s")),
+        );
+        if let Some(rate) = s.rate {
+            line.push_str(&format!(" rate={}/s,burst={}", rate.per_sec, rate.burst));
+        }
+        if let Some(topic) = &s.topic {
+            line.push_str(&format!(" topic={topic}"));
+        }
+        return Ok(Some(line));
+    }
+    let needed = if matches!(key, "topic" | "pin" | "unpin") { 1 } else { 2 };
+    if info.rank(actor) < needed {
+        return Err("NotPermitted");
+    }
+    let value = value.trim();
+    let pins = &mut info.settings.pins;
+    let event = match key {
+        "topic" => {
+            info.settings.topic = (!value.is_empty()).then(|| value.to_string());
+            format!("/topic {actor_nick} {value}")
+        }
+        "pin" => {
+            if value.is_empty() || value.contains(' ') {
+                return Err("BadValue");
+            }
+            if pins.len() >= MAX_PINS {
+                return Err("TooManyPins");
+            }
+            pins.push(value.to_string());
+            pins_frame(actor_nick, pins)
+        }
+        "unpin" => {
+            // 不带序号清空全部，带序号（从 1 开始）删一条
+            match value {
+                "" => pins.clear(),
+                n => {
+                    let n: usize = n.parse().map_err(|_| "BadValue")?;
+                    if n == 0 || n > pins.len() {
+                        return Err("BadValue");
+                    }
+                    pins.remove(n - 1);
+                }
+            }
+            pins_frame(actor_nick, pins)
+        }
+        "persist" => {
+            info.persistent = match value {
+                "on" if state.registry.enabled() => true,
+                "on" => return Err("PersistenceDisabled"),
+                "off" => false,
+                _ => return Err("BadValue"),
+            };
+            format!("/room_set {key} {actor_nick} {value}")
+        }
+        _ => {
+            let shown = registry::apply(&mut info.settings, key, value)?;
+            info.settings.retention.prune(&mut info.history);
+            format!("/room_set {key} {actor_nick} {shown}")
+        }
+    };
+    info.announce(event);
+    // persist off 也要写一次，把房间从状态文件里去掉
+    save_registry(&state.registry, &map);
+    Ok(None)
+}
+
+/// 一个连接对某房间的订阅：转发任务把广播搬进连接的 mpsc
+struct Subscription {
+    task: JoinHandle<()>,
+    // 放在最后：先停转发，再广播离开
+    _guard: RoomGuard,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn subscribe(
+    state: &State,
+    room_id: &str,
+    conn_id: u64,
+    nickname: &str,
+    tx: broadcast::Sender<Event>,
+    fwd: mpsc::Sender<(String, String)>,
+    ctl: mpsc::UnboundedSender<Control>,
+) -> Subscription {
+    // guard 先建好，确保后续任何退出都会调用它的 Drop
+    let guard = RoomGuard {
+        state: state.clone(),
+        room_id: room_id.to_string(),
+        conn_id,
+    };
+    // 发送加入通知
+    let _ = tx.send((0, format!("⚡ [{}] joined.", nickname)));
+    let room = room_id.to_string();
+    // 持锁同时取历史快照和订阅：之后的消息都走广播，不重不漏
+    let (mut rx, mut last, backlog) = {
+        let mut map = state.rooms.lock().unwrap();
+        let mut backlog = Vec::new();
+        let mut last = 0;
+        if let Some(info) = map.get_mut(room_id) {
+            backlog = info.backlog_since(0);
+            last = info.seq;
+            // 主题与置顶只发给新成员
+            if let Some(topic) = &info.settings.topic {
+                backlog.push(format!("/topic - {topic}"));
+            }
+            if !info.settings.pins.is_empty() {
+                backlog.push(pins_frame("-", &info.settings.pins));
+            }
+        }
+        (tx.subscribe(), last, backlog)
+    };
+    let policy = state.config().backpressure.slow_consumer;
+    let state_ref = state.clone();
+    let task = tokio::spawn(async move {
+        for line in backlog {
+            if fwd.send((room.clone(), line)).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            let frames = match rx.recv().await {
+                Ok((seq, msg)) => {
+                    // 补发过的聊天消息不再重复转发
+                    if seq != 0 {
+                        if seq <= last {
+                            continue;
+                        }
+                        last = seq;
+                    }
+                    vec![msg]
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::info!(room = %room, missed, "connection fell behind the room broadcast");
+                    state_ref.metrics.lagged_receivers.inc();
+                    state_ref.metrics.lagged_messages.add(missed);
+                    if policy == SlowConsumer::Disconnect {
+                        let _ = ctl.send(Control::Lagged { room: room.clone(), missed });
+                        break;
+                    }
+                    // 告诉客户端丢了多少，再从历史里补发还能找回的聊天消息
+                    let mut frames = vec![format!("/lagged {missed}")];
+                    if let Some(info) = state_ref.rooms.lock().unwrap().get_mut(&room) {
+                        frames.extend(info.backlog_since(last));
+                        last = info.seq;
+                    }
+                    frames
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            for frame in frames {
+                if fwd.send((room.clone(), frame)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }.in_current_span());
+    {
+        let map = state.rooms.lock().unwrap();
+        if let Some(info) = map.get(room_id) {
+            broadcast_member_list(info);   // <-- 现在新客户端已经订阅，一定能收到
+        }
+    }
+    Subscription { task, _guard: guard }
+}
+
+/// 管理接口 `reload` 时重新生成配置
+type Reload = Arc<dyn Fn() -> Result<ServerConfig> + Send + Sync>;
+
+/// 请求关停：第一次平滑关停，关停过程中再次调用则不再等待客户端
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<u32>>);
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        ShutdownHandle(Arc::new(watch::channel(0).0))
+    }
+
+    pub fn shutdown(&self) {
+        self.0.send_modify(|n| *n += 1);
+    }
+
+    /// 等到累计收到 `times` 次关停请求
+    async fn requested(&self, times: u32) {
+        let _ = self.0.subscribe().wait_for(|n| *n >= times).await;
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+pub struct ServerBuilder {
+    config:   Option<ServerConfig>,
+    bind:     Vec<String>,
+    shutdown: Option<ShutdownHandle>,
+    reload:   Option<Reload>,
+}
+
+impl ServerBuilder {
+    /// 不设则用 [`ServerConfig::default`]（默认口令，仅供测试）
+    pub fn config(mut self, cfg: ServerConfig) -> Self {
+        self.config = Some(cfg);
+        self
+    }
+
+    /// 追加监听地址，设置后取代配置里的 `bind`；端口写 0 由系统分配，见 [`Server::local_addrs`]
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.bind.push(addr.into());
+        self
+    }
+
+    /// 外部持有的关停句柄；不设则由 [`Server::shutdown_handle`] 取得
+    pub fn shutdown_handle(mut self, handle: ShutdownHandle) -> Self {
+        self.shutdown = Some(handle);
+        self
+    }
+
+    /// 管理接口 `reload` 时调用，返回新的配置；不设则 `reload` 沿用当前配置
+    pub fn reload(mut self, f: impl Fn() -> Result<ServerConfig> + Send + Sync + 'static) -> Self {
+        self.reload = Some(Arc::new(f));
+        self
+    }
+
+    /// 生效配置、恢复持久房间并绑定所有监听地址（以及指标端口、管理套接字）
+    pub async fn build(self) -> Result<Server> {
+        let mut cfg = self.config.unwrap_or_default();
+        if !self.bind.is_empty() {
+            cfg.bind = self.bind;
+        }
+        let key = cfg.password_hash()?;
+
+        let mut registered = HashMap::new();
+        let registry = match &cfg.state {
+            Some(path) => {
+                let (registry, rooms) = Registry::open(path)?;
+                for room in rooms {
+                    registered.insert(room.id.clone(), RoomInfo::restore(room, &cfg));
+                }
+                tracing::info!(rooms = registered.len(), path = %path.display(), "loaded persistent rooms");
+                registry
+            }
+            None => Registry::ephemeral(),
+        };
+        let state: State = Arc::new(ServerState {
+            rooms:    Mutex::new(registered),
+            config:   RwLock::new(cfg.clone()),
+            keys:     RwLock::new(ServerKeys { current: key, previous: None }),
+            governor: Governor::default(),
+            registry,
+            metrics:  Metrics::default(),
+            started:  Instant::now(),
+        });
+
+        let mut listeners = Vec::new();
+        for bind in &cfg.bind {
+            let bind_addr = ServerAddr::parse_with_port(bind, DEFAULT_PORT)?;
+            let listener = bind_addr.bind().await?;
+            tracing::info!(addr = %listener.local_addr()?, "listening");
+            listeners.push(listener);
+        }
+        if listeners.is_empty() {
+            bail!("no bind address configured");
+        }
+        let metrics_addr = match &cfg.metrics_bind {
+            Some(bind) => Some(metrics::spawn(bind, state.clone()).await?),
+            None => None,
+        };
+        let reload = self.reload.unwrap_or_else(|| {
+            let state = state.clone();
+            Arc::new(move || Ok(state.config().clone()))
+        });
+        if let Some(path) = &cfg.admin_socket {
+            #[cfg(unix)]
+            admin::spawn(path, state.clone(), reload)?;
+            #[cfg(not(unix))]
+            {
+                let _ = reload;
+                tracing::warn!(path = %path.display(), "the admin socket is only supported on Unix, ignoring");
+            }
+        }
+        Ok(Server {
+            listeners,
+            state,
+            shutdown: self.shutdown.unwrap_or_default(),
+            admin_socket: cfg.admin_socket,
+            metrics_addr,
+        })
+    }
+}
+
+/// 已绑定端口、尚未开始服务的服务器
+pub struct Server {
+    listeners:    Vec<TcpListener>,
+    state:        State,
+    shutdown:     ShutdownHandle,
+    admin_socket: Option<PathBuf>,
+    metrics_addr: Option<SocketAddr>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// 实际监听的地址（端口 0 时可由此得知分配到的端口）
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(|l| l.local_addr().ok()).collect()
+    }
+
+    /// 指标端口实际监听的地址，未配置 `metrics_bind` 时为 None
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 接受连接直到关停；平滑关停时等所有连接断开或宽限期结束后返回
+    pub async fn run(self) -> Result<()> {
+        let Server { listeners, state, shutdown, admin_socket, .. } = self;
+        let sweep = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let mut tick = tokio::time::interval(Duration::from_secs(60));
+                loop {
+                    tick.tick().await;
+                    let limits = state.config().limits.clone();
+                    state.governor.sweep(&limits);
+                }
+            }
+        });
+        // 每个连接持有一个接收端，全部断开后 closed() 返回
+        let deadline = Arc::new(watch::channel(None).0);
+        // 每个监听地址一个 accept 循环，共享同一张房间表
+        let accepts: Vec<_> = listeners.into_iter().map(|listener| {
+            let (state, deadline) = (state.clone(), deadline.clone());
+            tokio::spawn(async move {
+                loop {
+                    let (socket, addr) = listener.accept().await?;
+                    accept(socket, addr, &state, deadline.subscribe());
+                }
+                #[allow(unreachable_code)]
+                Ok::<(), std::io::Error>(())
+            })
+        }).collect();
+        let aborts: Vec<_> = accepts.iter().map(JoinHandle::abort_handle).collect();
+        let result = tokio::select! {
+            results = futures_util::future::join_all(accepts) => {
+                results.into_iter().try_for_each(|r| r?.map_err(anyhow::Error::from))
+            }
+            _ = shutdown.requested(1) => {
+                // 先停止接受新连接，再通知在线客户端，等它们断开或宽限期结束
+                for abort in aborts {
+                    abort.abort();
+                }
+                let grace = state.config().shutdown_grace_secs;
+                drain(&deadline, &shutdown, grace).await;
+                Ok(())
+            }
+        };
+        sweep.abort();
+        if let Some(path) = admin_socket {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+}
+
+/// 平滑关停：广播关停倒计时，等所有连接断开；宽限期过后或再次请求关停时直接返回
+async fn drain(deadline: &watch::Sender<Option<Instant>>, shutdown: &ShutdownHandle, grace: u64) {
+    let at = Instant::now() + Duration::from_secs(grace);
+    tracing::info!(grace_secs = grace, connections = deadline.receiver_count(), "shutting down");
+    deadline.send_replace(Some(at));
+    // 到点后连接自己断开，多留一秒让它们写完
+    let timeout = tokio::time::sleep_until((at + Duration::from_secs(1)).into());
+    tokio::select! {
+        _ = deadline.closed() => tracing::info!("all clients disconnected"),
+        _ = timeout => tracing::warn!(dropped = deadline.receiver_count(), "grace period over"),
+        _ = shutdown.requested(2) => tracing::warn!("second shutdown request, exiting now"),
+    }
+}
+
+fn accept(mut socket: TcpStream, addr: SocketAddr, state: &State, shutdown: Deadline) {
+    let permit = match governor::admit(state, addr.ip()) {
+        Ok(permit) => permit,
+        Err(why) => {
+            tracing::info!(peer = %addr, reason = why, "connection refused");
+            state.metrics.connections_rejected.inc();
+            tokio::spawn(async move {
+                let _ = socket.write_all(format!("ERR {why}\n").as_bytes()).await;
+            });
+            return;
+        }
+    };
+    let conn_id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed);
+    // 握手成功后再填 nick 与首个房间
+    let span = tracing::info_span!("conn", id = conn_id, peer = %addr, nick = Empty, room = Empty);
+    state.metrics.connections_accepted.inc();
+    let state = state.clone();
+    let panics = state.clone();
+    tokio::spawn(
+        AssertUnwindSafe(async move {
+            let _permit = permit;
+            tracing::debug!("connected");
+            if let Err(e) = handle_client(socket, addr, conn_id, state, shutdown).await {
+                tracing::warn!("connection error: {e:#}");
+            }
+            tracing::debug!("disconnected");
+        }.instrument(span))
+        .catch_unwind()
+        .map(move |res| {
+            if let Err(panic) = res {
+                panics.metrics.panics.inc();
+                tracing::error!(peer = %addr, "connection task panicked: {panic:?}");
+            }
+        }),
+    );
+}
+async fn handle_client(socket: TcpStream, peer: SocketAddr, conn_id: u64, state: State, shutdown: Deadline) -> Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    // 认证前只接受短帧，认证后放宽到配置的上限
+    let mut lines = FrameReader::new(reader, HANDSHAKE_MAX_FRAME);
+    let result = serve(&mut lines, &mut writer, peer, conn_id, state, shutdown).await;
+    if let Err(e) = &result {
+        if e.downcast_ref::<std::io::Error>().is_some_and(is_too_large) {
+            tracing::info!(max = lines.max(), "frame too large, disconnecting");
+            let _ = writer.write_all(format!("ERR {FRAME_TOO_LARGE}\n").as_bytes()).await;
+            return Ok(());
+        }
+    }
+    result
+}
+
+async fn serve(
+    lines: &mut FrameReader<OwnedReadHalf>,
+    writer: &mut OwnedWriteHalf,
+    peer: SocketAddr,
+    conn_id: u64,
+    state: State,
+    mut shutdown: Deadline,
+) -> Result<()> {
+    let started = Instant::now();
+    /* ---------- ②-a 等待客户端 AUTH ---------- */
+    let enc_line = match lines.next_line().await? {
+        Some(l) => l.trim_end().to_owned(),
+        None    => return Ok(()),
+    };
+    
+    // 先限速再验证：口令错误时外层就解不开，同样算一次失败
+    let limits = state.config().limits.clone();
+    if let Err(why) = state.governor.check_auth(peer.ip(), &limits) {
+        audit!(event = "auth_refused", peer = %peer.ip(), reason = %why);
+        writer.write_all(format!("ERR {why}\n").as_bytes()).await?;
+        return Ok(());
+    }
+    // 本连接的服务器密钥：能解开 AUTH 的那一个（轮换窗口内也可能是旧密钥），
+    // 之后再轮换口令不影响已建立的会话
+    let keys = state.accepted_keys();
+    let opened = keys.iter().find_map(|k| aead_open(k, &enc_line).map(|line| (*k, line)));
+    let key = opened.as_ref().map_or(keys[0], |(k, _)| *k);
+    let auth_ok = match opened {
+        Some((_, auth_line)) => match auth_line.strip_prefix("AUTH ") {
+            Some(auth) => dec_auth(auth, &key),
+            None => {
+                writer.write_all(b"ERR NeedAUTH\n").await?;
+                return Ok(());
+            }
+        },
+        None => false,
+    };
+    state.governor.auth_result(peer.ip(), auth_ok, &limits);
+    if !auth_ok {
+        state.metrics.auth_failures.inc();
+        audit!(event = "auth_failure", peer = %peer.ip());
+        writer.write_all(b"ERR BadAuth\n").await?;
+        return Ok(());
+    }
+    if key != keys[0] {
+        audit!(event = "auth_previous_password", peer = %peer.ip());
+    }
+    let cipher = seal_line(&key, "OK".to_string());
+    writer.write_all(&cipher).await?;
+    state.metrics.handshake_auth.observe(started.elapsed());
+    // 先拷出来：守卫不能活到下面再次读配置
+    let (max_frame, hashed_rooms, max_message) = {
+        let cfg = state.config();
+        (cfg.max_frame_bytes, cfg.hashed_rooms, cfg.max_message_bytes)
+    };
+    lines.set_max(max_frame);
+    /* ---------- ① 发送房间列表 ---------- */
+    // 隐藏房间不列出；哈希模式下房间号本来就不可读，只告诉客户端要用哈希
+    let room_line = {
+        let map = state.rooms.lock().unwrap();
+        let mut line = format!("ROOMS +max_frame={max_frame} +salt={}", state.registry.salt());
+        if hashed_rooms {
+            line.push_str(" +hashed");
+        } else {
+            for (id, info) in map.iter() {
+                if info.settings.visibility == Visibility::Listed {
+                    line.push(' ');
+                    line.push_str(id);
+                }
+            }
+        }
+        line
+    };
+    writer.write_all(&seal_line(&key, room_line)).await?;
+
+    /* ---------- ② 读取客户端指令 ---------- */
+    let cmd = match lines.next_line().await? {
+        Some(c) => c.trim_end().to_owned(),
+        None => return Ok(()),
+    };
+    let entering = Instant::now();
+    let cmd = aead_open(&key, &cmd).unwrap_or(cmd);
+    let mut parts = cmd.split_whitespace();
+    
+    let action   = parts.next().unwrap_or_default();
+    let room_id  = parts.next().unwrap_or_default().to_string();
+    let cred     = parts.next().unwrap_or_default().to_string();
+    let mut nickname = parts.next().unwrap_or_default().to_string();
+    // 客户端自报的身份统一加 `key:` 前缀，与服务器按对端地址生成的 `ip:` 身份分开，
+    // 谁也冒充不了旧客户端的房主或绕过按 IP 的封禁；旧客户端不带身份标识，退化为按 IP 识别
+    let identity = match parts.next() {
+        Some(id) if id.starts_with("ip:") => {
+            writer.write_all(b"ERR BadIdentity\n").await?;
+            return Ok(());
+        }
+        Some(id) => format!("key:{id}"),
+        None => format!("ip:{}", peer.ip()),
+    };
+    let hidden = parts.next() == Some("hidden");
+
+    if room_id.is_empty() || cred.is_empty() || nickname.is_empty() {
+        writer.write_all(b"ERR InvalidCmd\n").await?;
+        writer.write_all(b"\n").await?;
+        return Ok(());
+    }
+
+    if !valid_nick(&nickname) {
+        writer.write_all(b"ERR BadNick\n").await?;
+        return Ok(());
+    }
+
+    /* ---------- ③ 同步处理房间表（无 await） ---------- */
+    let (ctl_tx, mut ctl_rx) = mpsc::unbounded_channel::<Control>();
+    let member = |nickname: &str| Member {
+        nickname: nickname.to_string(),
+        identity: identity.clone(),
+        ip: peer.ip(),
+        ctl: ctl_tx.clone(),
+    };
+    // 首个房间重名时自动加后缀，随后用 `/nick` 告诉客户端
+    let wanted = nickname.clone();
+    let handshake = enter_room(&state, action, &room_id, &cred, conn_id, member(&nickname), hidden, true);
+
+    /* ---------- ④ 发送握手结果 & 订阅首个房间 ---------- */
+    let room_tx = match handshake {
+        Ok((tx, entered_as)) => {
+            nickname = entered_as;
+            let cipher = seal_line(&key, "OK".to_string());
+            writer.write_all(&cipher).await?;
+            state.metrics.handshake_enter.observe(entering.elapsed());
+            let span = tracing::Span::current();
+            span.record("nick", nickname.as_str());
+            span.record("room", room_id.as_str());
+            if nickname != wanted {
+                writer.write_all(&seal_line(&key, format!("/nick {nickname}"))).await?;
+            }
+            let motd = state.config().motd.clone();
+            for line in motd.lines() {
+                writer.write_all(&seal_line(&key, format!("/motd {line}"))).await?;
+            }
+            tx
+        }
+        Err(why) => {
+            writer.write_all(format!("ERR {why}\n").as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    // 同一连接可订阅多个房间：room_id → Subscription
+    // 广播 → 连接的队列：有界时写得慢的客户端会让广播滞后，按 slow_consumer 处理
+    // 先拷出来再 match：守卫活到 match 结束，重复 read 会在 reload 等写锁时死锁
+    let bp = state.config().backpressure.clone();
+    let queue = match bp.slow_consumer {
+        SlowConsumer::Buffer => tokio::sync::Semaphore::MAX_PERMITS,
+        _ => bp.client_queue.max(1),
+    };
+    let (fwd_tx, mut fwd_rx) = mpsc::channel::<(String, String)>(queue);
+    let mut subs: HashMap<String, Subscription> = HashMap::new();
+    subs.insert(room_id.clone(), subscribe(&state, &room_id, conn_id, &nickname, room_tx, fwd_tx.clone(), ctl_tx.clone()));
+
+    /* ---------- ⑤ 正式聊天循环 ---------- */
+    let mut flood = FloodGuard::new(state.config().flood.clone());
+    let closing = tokio::time::sleep(Duration::MAX);
+    tokio::pin!(closing);
+    let mut draining = false;
+    loop {
+        tokio::select! {
+            Ok(deadline) = async { shutdown.wait_for(Option::is_some).await.map(|d| *d) }, if !draining => {
+                // 服务器即将关停：告诉客户端还剩几秒，到点断开
+                let deadline = deadline.unwrap_or_else(Instant::now);
+                draining = true;
+                closing.as_mut().reset(deadline.into());
+                let secs = deadline.saturating_duration_since(Instant::now()).as_secs();
+                writer.write_all(&seal_line(&key, format!("/shutdown {secs}"))).await?;
+            }
+            _ = &mut closing, if draining => break,
+            result = lines.next_line() => {
+                let Some(line) = result? else { break };
+                if line == "$$ping$$" {
+                    let _ = writer.write_all(b"/ping_ack\n").await;
+                    continue;
+                }
+                let Some(plain) = aead_open(&key, &line) else { continue };
+
+                // #<room> <ENC:…>：转发到对应房间
+                if let Some(rest) = plain.strip_prefix('#') {
+                    if let Some((room, body)) = rest.split_once(' ') {
+                        if subs.contains_key(room) && plain.len() > max_message {
+                            let frame = seal_line(&key, format!("#{room} /mod_err TooLarge"));
+                            writer.write_all(&frame).await?;
+                        } else if subs.contains_key(room) {
+                            let reply = post(&state, &mut flood, room, &nickname, &identity, &ctl_tx, body);
+                            if let Some(reply) = reply {
+                                let frame = seal_line(&key, format!("#{room} {reply}"));
+                                writer.write_all(&frame).await?;
+                            }
+                        }
+                    }
+                } else if let Some(args) = plain.strip_prefix("/join ") {
+                    let mut it = args.split_whitespace();
+                    let room = it.next().unwrap_or_default().to_string();
+                    let cred = it.next().unwrap_or_default();
+                    let hidden = it.next() == Some("hidden");
+                    let reply = if room.is_empty() || cred.is_empty() {
+                        format!("/join_err {room} InvalidCmd")
+                    } else if subs.contains_key(&room) {
+                        format!("/join_ok {room}")
+                    } else {
+                        match enter_room(&state, "ENTER", &room, cred, conn_id, member(&nickname), hidden, false) {
+                            Ok((tx, _)) => {
+                                subs.insert(room.clone(), subscribe(&state, &room, conn_id, &nickname, tx, fwd_tx.clone(), ctl_tx.clone()));
+                                format!("/join_ok {room}")
+                            }
+                            Err(why) => format!("/join_err {room} {why}"),
+                        }
+                    };
+                    writer.write_all(&seal_line(&key, reply)).await?;
+                } else if let Some(new) = plain.strip_prefix("/nick ") {
+                    let new = new.trim();
+                    let reply = match rename(&state, conn_id, &subs, &nickname, new) {
+                        Ok(()) => {
+                            nickname = new.to_string();
+                            tracing::Span::current().record("nick", nickname.as_str());
+                            format!("/nick {nickname}")
+                        }
+                        Err(why) => format!("/nick_err {why}"),
+                    };
+                    writer.write_all(&seal_line(&key, reply)).await?;
+                } else if let Some(rest) = plain.strip_prefix("/mod ") {
+                    let (room, args) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let result = if subs.contains_key(room) {
+                        moderate(&state, room, Some(&identity), &nickname, args)
+                    } else {
+                        Err("NotInRoom")
+                    };
+                    if let Err(why) = result {
+                        writer.write_all(&seal_line(&key, format!("#{room} /mod_err {why}"))).await?;
+                    }
+                } else if let Some(rest) = plain.strip_prefix("/room ") {
+                    let (room, args) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let result = if subs.contains_key(room) {
+                        configure(&state, room, &identity, &nickname, args)
+                    } else {
+                        Err("NotInRoom")
+                    };
+                    let reply = match result {
+                        Ok(Some(info)) => format!("#{room} {info}"),
+                        Ok(None) => continue,
+                        Err(why) => format!("#{room} /mod_err {why}"),
+                    };
+                    writer.write_all(&seal_line(&key, reply)).await?;
+                } else if let Some(room) = plain.strip_prefix("/leave ") {
+                    subs.remove(room.trim());
+                    flood.forget(room.trim());
+                    if subs.is_empty() {
+                        break;
+                    }
+                }
+            }
+            Some(ctl) = ctl_rx.recv() => match ctl {
+                Control::Kicked { room, by, ban } => {
+                    // 先退订（guard 广播离开），再告诉客户端；连接保持，其他房间不受影响
+                    flood.forget(&room);
+                    if subs.remove(&room).is_some() {
+                        let kind = if ban { "ban" } else { "kick" };
+                        writer.write_all(&seal_line(&key, format!("#{room} /kicked {by} {kind}"))).await?;
+                    }
+                }
+                Control::Announce { text } => {
+                    writer.write_all(&seal_line(&key, format!("/announce {text}"))).await?;
+                }
+                Control::Lagged { room, missed } => {
+                    tracing::info!(room = %room, missed, "too slow for the room broadcast, disconnecting");
+                    let _ = writer.write_all(&seal_line(&key, format!("#{room} /lagged {missed}"))).await;
+                    break;
+                }
+            },
+            Some((room, msg)) = fwd_rx.recv() => {
+                let frame = seal_line(&key, format!("#{room} {msg}"));
+                if writer.write_all(&frame).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // 注意：不需要手动发送离开或回收房间，subs 里的 guard 会在此作用域结束时自动执行
+    Ok(())
+}
@@ -0,0 +1,247 @@
+// server/registry.rs
+//! 持久化房间登记表：`--state <文件>` 开启，启动时读回
+//!
+//! 只保存房间的身份与设置（凭据、房主、管理员、封禁、房间设置），不保存成员和聊天内容。
+//! 持久房间在没人时也不会被回收，别人无法用另一个密码重新 CREATE 抢占。
+//!
+//! 同一文件里还保存服务器的身份盐：客户端用它派生发给本服务器的身份标识，
+//! 所以轮换服务器口令后房主、管理员与封禁仍然有效。
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Listed,
+    /// 不出现在 ROOMS 列表里
+    Hidden,
+}
+
+impl Visibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Listed => "listed",
+            Visibility::Hidden => "hidden",
+        }
+    }
+}
+
+/// 历史消息保留策略：新成员加入时回放（内容仍是房间密钥密文）
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    /// 最多保留条数，0 表示不保留
+    pub keep:    usize,
+    /// 超过该秒数的消息丢弃
+    pub max_age: Option<u64>,
+}
+
+impl Retention {
+    /// 丢掉超出条数或过期的历史
+    pub fn prune(&self, history: &mut VecDeque<(Instant, String)>) {
+        while history.len() > self.keep {
+            history.pop_front();
+        }
+        if let Some(age) = self.max_age {
+            let age = Duration::from_secs(age);
+            while history.front().is_some_and(|(at, _)| at.elapsed() > age) {
+                history.pop_front();
+            }
+        }
+    }
+}
+
+/// 房间的发言频率限制：每秒条数与突发条数
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rate {
+    pub per_sec: f64,
+    pub burst:   u32,
+}
+
+/// 房主可修改的房间设置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoomSettings {
+    /// 房间密钥加密后的主题（服务器看不到明文）
+    pub topic:      Option<String>,
+    /// 房间密钥加密后的置顶消息
+    pub pins:       Vec<String>,
+    /// 成员上限
+    pub capacity:   Option<usize>,
+    pub visibility: Visibility,
+    pub retention:  Retention,
+    /// 比服务器全局限制更严的发言频率
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate:       Option<Rate>,
+}
+
+/// 状态文件里的一个房间
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedRoom {
+    pub id:         String,
+    pub credential: String,
+    pub owner:      String,
+    #[serde(default)]
+    pub mods:       HashSet<String>,
+    #[serde(default)]
+    pub bans:       HashSet<String>,
+    #[serde(default)]
+    pub settings:   RoomSettings,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    salt:  Option<String>,
+    #[serde(default)]
+    rooms: Vec<PersistedRoom>,
+}
+
+/// 一个 `Server` 的状态文件与身份盐
+pub struct Registry {
+    path: Option<PathBuf>,
+    /// 服务器身份盐（十六进制），在 ROOMS 横幅里以 `+salt=` 下发；
+    /// 不开持久化时每次启动重新生成，反正房间也不会保留
+    salt: String,
+}
+
+impl Registry {
+    /// 不开持久化
+    pub fn ephemeral() -> Self {
+        let mut salt = [0u8; 16];
+        rand::RngCore::fill_bytes(&mut rand::rng(), &mut salt);
+        Registry { path: None, salt: hex::encode(salt) }
+    }
+
+    /// 启用持久化并读回已登记的房间；文件不存在视为空
+    pub fn open(path: &Path) -> Result<(Self, Vec<PersistedRoom>)> {
+        let mut state: StateFile = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| anyhow!("{}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StateFile::default(),
+            Err(e) => return Err(e.into()),
+        };
+        for room in &mut state.rooms {
+            room.owner = namespaced(std::mem::take(&mut room.owner));
+            room.mods = std::mem::take(&mut room.mods).into_iter().map(namespaced).collect();
+            room.bans = std::mem::take(&mut room.bans).into_iter().map(namespaced).collect();
+        }
+        let fresh = state.salt.is_none();
+        let registry = Registry {
+            path: Some(path.to_path_buf()),
+            salt: state.salt.unwrap_or_else(|| Self::ephemeral().salt),
+        };
+        // 第一次运行（或旧版本的状态文件）：立即写回新生成的盐，重启后保持不变
+        if fresh {
+            registry.save(state.rooms.clone())?;
+        }
+        Ok((registry, state.rooms))
+    }
+
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// 整表写回（先写临时文件再改名，避免写一半崩溃留下坏文件）
+    pub fn save(&self, rooms: Vec<PersistedRoom>) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&StateFile { salt: Some(self.salt.clone()), rooms })?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// 旧版本的状态文件里客户端身份没有 `key:` 前缀，读回时补上
+fn namespaced(identity: String) -> String {
+    if identity.starts_with("ip:") || identity.starts_with("key:") {
+        identity
+    } else {
+        format!("key:{identity}")
+    }
+}
+
+/// `/room <key> <value>`：解析并修改一项设置，返回规范化后的值用于广播
+pub fn apply(settings: &mut RoomSettings, key: &str, value: &str) -> Result<String, &'static str> {
+    match key {
+        "capacity" => {
+            settings.capacity = match value {
+                "none" | "0" => None,
+                n => Some(n.parse().map_err(|_| "BadValue")?),
+            };
+            Ok(settings.capacity.map_or("none".into(), |n| n.to_string()))
+        }
+        "visibility" => {
+            settings.visibility = match value {
+                "listed" => Visibility::Listed,
+                "hidden" => Visibility::Hidden,
+                _ => return Err("BadValue"),
+            };
+            Ok(value.to_string())
+        }
+        "retention" => {
+            // `<条数> [时长]`，如 `200 24h`；`0` 关闭
+            let mut it = value.split_whitespace();
+            let keep = it.next().and_then(|n| n.parse().ok()).ok_or("BadValue")?;
+            let max_age = match it.next() {
+                Some(d) => Some(parse_duration(d).ok_or("BadDuration")?),
+                None => None,
+            };
+            settings.retention = Retention { keep, max_age };
+            Ok(match max_age {
+                Some(age) => format!("{keep} {age}s"),
+                None => keep.to_string(),
+            })
+        }
+        "rate" => {
+            // `<每秒条数> [突发条数]`，如 `0.5 3`；`none` 只受服务器全局限制
+            if value == "none" {
+                settings.rate = None;
+                return Ok("none".into());
+            }
+            let mut it = value.split_whitespace();
+            let per_sec: f64 = it.next().and_then(|n| n.parse().ok()).ok_or("BadValue")?;
+            if !(per_sec > 0.0 && per_sec.is_finite()) {
+                return Err("BadValue");
+            }
+            let burst = match it.next() {
+                Some(n) => n.parse().map_err(|_| "BadValue")?,
+                None => per_sec.ceil() as u32,
+            };
+            settings.rate = Some(Rate { per_sec, burst });
+            Ok(format!("{per_sec}/s burst {burst}"))
+        }
+        _ => Err("UnknownSetting"),
+    }
+}
+
+/// 时长上限：一年。再长的禁言与永久无异，还会让 `Instant` 加法溢出
+const MAX_DURATION_SECS: u64 = 365 * 86400;
+
+/// `10`、`90s`、`5m`、`2h`、`1d` → 秒；超过一年视为无效
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = num.parse().ok()?;
+    let mul = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    n.checked_mul(mul).filter(|&secs| secs <= MAX_DURATION_SECS)
+}